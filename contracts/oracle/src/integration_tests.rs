@@ -5,7 +5,6 @@ mod tests {
         GetAllPricesResponse, GetCurrentPriceResponse, GetOldPricesResponse, InstantiateMsg,
         PriceMsg, QueryMsg,
     };
-    use crate::state::Price;
     use cosmwasm_std::{Addr, Coin, Empty, Uint128};
     use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
 
@@ -3,8 +3,16 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use orderbook::msg::{ExecuteMsg, GetCountResponse, InstantiateMsg, QueryMsg};
-use orderbook::state::State;
+use orderbook::msg::{
+    AccountFeesResponse, AdlRankingResponse, BatchOrdersResponse, ConfigResponse,
+    DelegatesResponse, ExecuteMsg, FeeTiersResponse, FundingResponse, InstantiateMsg,
+    InsuranceFundResponse, LastTradePriceResponse, LiquidatablePositionsResponse,
+    ListMarketsResponse, MarginAccountResponse, MarketResponse, MarketStatsResponse,
+    OrderBookResponse, OrdersResponse, PlacedOrder, PositionResponse, PositionsResponse, QueryMsg,
+    ReceiveMsg, ReferralRewardsResponse, ReferralStatsResponse, SubAccountsResponse,
+    TradesResponse, TriggerOrdersResponse,
+};
+use orderbook::state::{Config, Market, Order, Position};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -15,6 +23,33 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(State), &out_dir);
-    export_schema(&schema_for!(GetCountResponse), &out_dir);
+    export_schema(&schema_for!(ReceiveMsg), &out_dir);
+    export_schema(&schema_for!(Config), &out_dir);
+    export_schema(&schema_for!(Market), &out_dir);
+    export_schema(&schema_for!(Order), &out_dir);
+    export_schema(&schema_for!(Position), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(MarketResponse), &out_dir);
+    export_schema(&schema_for!(ListMarketsResponse), &out_dir);
+    export_schema(&schema_for!(OrderBookResponse), &out_dir);
+    export_schema(&schema_for!(OrdersResponse), &out_dir);
+    export_schema(&schema_for!(PositionResponse), &out_dir);
+    export_schema(&schema_for!(PositionsResponse), &out_dir);
+    export_schema(&schema_for!(MarginAccountResponse), &out_dir);
+    export_schema(&schema_for!(LiquidatablePositionsResponse), &out_dir);
+    export_schema(&schema_for!(InsuranceFundResponse), &out_dir);
+    export_schema(&schema_for!(FundingResponse), &out_dir);
+    export_schema(&schema_for!(MarketStatsResponse), &out_dir);
+    export_schema(&schema_for!(TriggerOrdersResponse), &out_dir);
+    export_schema(&schema_for!(FeeTiersResponse), &out_dir);
+    export_schema(&schema_for!(AccountFeesResponse), &out_dir);
+    export_schema(&schema_for!(AdlRankingResponse), &out_dir);
+    export_schema(&schema_for!(SubAccountsResponse), &out_dir);
+    export_schema(&schema_for!(DelegatesResponse), &out_dir);
+    export_schema(&schema_for!(BatchOrdersResponse), &out_dir);
+    export_schema(&schema_for!(PlacedOrder), &out_dir);
+    export_schema(&schema_for!(TradesResponse), &out_dir);
+    export_schema(&schema_for!(LastTradePriceResponse), &out_dir);
+    export_schema(&schema_for!(ReferralStatsResponse), &out_dir);
+    export_schema(&schema_for!(ReferralRewardsResponse), &out_dir);
 }
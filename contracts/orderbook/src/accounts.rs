@@ -0,0 +1,35 @@
+use cosmwasm_std::{Addr, Api, StdError, StdResult, Storage};
+
+use crate::state::ACCOUNT_OWNERS;
+
+/// Trading address of an owner's numbered sub-account. Sub-account zero is
+/// the owner's own wallet. Sub-account addresses contain a `/`, so they can
+/// never collide with a real wallet.
+pub fn sub_account_addr(owner: &Addr, sub_account: u32) -> Addr {
+    match sub_account {
+        0 => owner.clone(),
+        n => Addr::unchecked(format!("{}/{}", owner, n)),
+    }
+}
+
+/// Validates a trading account given either as a wallet address or as an
+/// `owner/n` sub-account address.
+pub fn validate_account(api: &dyn Api, account: &str) -> StdResult<Addr> {
+    match account.split_once('/') {
+        None => api.addr_validate(account),
+        Some((owner, sub_account)) => {
+            let owner = api.addr_validate(owner)?;
+            let sub_account = sub_account.parse::<u32>().map_err(|_| {
+                StdError::generic_err(format!("Invalid sub-account in {}", account))
+            })?;
+            Ok(sub_account_addr(&owner, sub_account))
+        }
+    }
+}
+
+/// Wallet that owns a trading account and receives its payouts.
+pub fn account_owner(storage: &dyn Storage, account: &Addr) -> StdResult<Addr> {
+    Ok(ACCOUNT_OWNERS
+        .may_load(storage, account)?
+        .unwrap_or_else(|| account.clone()))
+}
@@ -0,0 +1,263 @@
+use cosmwasm_std::{Addr, BlockInfo, Int128, Order as SortOrder, StdResult, Storage, Uint128};
+
+use crate::accounts::account_owner;
+use crate::msg::PriceLevel;
+use crate::position::signed_size;
+use crate::state::{book_prefix, orders, Order, Side, POSITIONS};
+
+/// A match between an incoming order and a resting one, at the resting price.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub maker_order_id: u64,
+    pub maker: Addr,
+    pub price: Uint128,
+    pub size: Uint128,
+    /// Margin released from each order's reservation for the filled size.
+    pub maker_margin: Uint128,
+    pub taker_margin: Uint128,
+}
+
+/// Takes the share of an order's reserved margin that covers `size` of its
+/// remaining size, all of it once the order is filled.
+fn release_margin(order: &mut Order, size: Uint128) -> Uint128 {
+    let margin = order.margin.multiply_ratio(size, order.remaining());
+    order.filled += size;
+    if order.remaining().is_zero() {
+        std::mem::take(&mut order.margin)
+    } else {
+        order.margin -= margin;
+        margin
+    }
+}
+
+/// Shrinks an order's remaining size to `size`, returning the share of its
+/// reserved margin that no longer backs it.
+fn shrink_order(order: &mut Order, size: Uint128) -> Uint128 {
+    let excess = order.remaining() - size;
+    let refund = order.margin.multiply_ratio(excess, order.remaining());
+    order.size -= excess;
+    order.margin -= refund;
+    refund
+}
+
+/// Size an order of `owner`'s on `side` can fill, `pending` being fills of
+/// the owner's not yet applied to its position in the market. A reduce-only
+/// order fills up to the opposite position, without growing or flipping it,
+/// and any other order until the position reaches `max_size`.
+pub fn fillable_size(
+    storage: &dyn Storage,
+    owner: &Addr,
+    market_id: u64,
+    side: Side,
+    pending: Int128,
+    reduce_only: bool,
+    max_size: Uint128,
+) -> StdResult<Uint128> {
+    let size = POSITIONS
+        .may_load(storage, (owner, market_id))?
+        .map(|position| position.size)
+        .unwrap_or_default()
+        .checked_add(pending)?
+        .i128();
+    // the position measured in the direction the order moves it
+    let size = match side {
+        Side::Buy => size,
+        Side::Sell => -size,
+    };
+    let room = match reduce_only {
+        true => -size,
+        false => max_size.u128() as i128 - size,
+    };
+    Ok(Uint128::new(room.max(0) as u128))
+}
+
+/// Resting orders on `side` of a market in price-time priority, leaving out
+/// those expired at `block`.
+fn live_orders<'a>(
+    storage: &'a dyn Storage,
+    block: &'a BlockInfo,
+    market_id: u64,
+    side: Side,
+) -> impl Iterator<Item = StdResult<(u64, Order)>> + 'a {
+    orders()
+        .idx
+        .book
+        .sub_prefix(book_prefix(market_id, side))
+        .range(storage, None, None, SortOrder::Ascending)
+        .filter(move |item| match item {
+            Ok((_, order)) => !order.expires.is_expired(block),
+            Err(_) => true,
+        })
+}
+
+/// Best live order on `side` of a market, oldest first among equal prices.
+pub fn best_order(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    market_id: u64,
+    side: Side,
+) -> StdResult<Option<(u64, Order)>> {
+    live_orders(storage, block, market_id, side)
+        .next()
+        .transpose()
+}
+
+/// Whether `taker` would trade against a resting order at `maker_price`.
+pub fn crosses(taker: &Order, maker_price: Uint128) -> bool {
+    match taker.side {
+        Side::Buy => maker_price <= taker.price,
+        Side::Sell => maker_price >= taker.price,
+    }
+}
+
+/// Margin released from resting orders clamped while matching, by owner.
+pub type Refunds = Vec<(Addr, Uint128)>;
+
+/// Matches `taker` against the opposite side of its market in price-time
+/// priority until it is filled or stops crossing, skipping expired orders.
+/// Resting orders are updated in place and `taker.filled` and `taker.margin`
+/// are advanced; the taker itself is not stored. Resting orders are clamped
+/// to what their owner's position, as of the fills before them, lets them
+/// fill: reduce-only orders to the opposite position and the rest to
+/// `max_bet_size`. Orders clamped to nothing are removed.
+///
+/// Resting orders of any account of the taker's own wallet are cancelled
+/// instead of traded against, so that no one can wash volume into a cheaper
+/// fee tier. Their margin is refunded like that of clamped orders.
+pub fn match_order(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    taker: &mut Order,
+    max_bet_size: Uint128,
+) -> StdResult<(Vec<Fill>, Refunds)> {
+    let mut fills: Vec<Fill> = vec![];
+    let mut refunds = vec![];
+    let taker_wallet = account_owner(storage, &taker.owner)?;
+    while !taker.remaining().is_zero() {
+        let (maker_order_id, mut maker) =
+            match best_order(storage, block, taker.market_id, taker.side.opposite())? {
+                Some(best) => best,
+                None => break,
+            };
+        if !crosses(taker, maker.price) {
+            break;
+        }
+        if account_owner(storage, &maker.owner)? == taker_wallet {
+            refunds.push((maker.owner, maker.margin));
+            orders().remove(storage, maker_order_id)?;
+            continue;
+        }
+        let filled = fills
+            .iter()
+            .filter(|fill| fill.maker == maker.owner)
+            .map(|fill| fill.size)
+            .sum();
+        let fillable = fillable_size(
+            storage,
+            &maker.owner,
+            maker.market_id,
+            maker.side,
+            signed_size(maker.side, filled)?,
+            maker.reduce_only,
+            max_bet_size,
+        )?;
+        if maker.remaining() > fillable {
+            refunds.push((maker.owner.clone(), shrink_order(&mut maker, fillable)));
+            if maker.remaining().is_zero() {
+                orders().remove(storage, maker_order_id)?;
+                continue;
+            }
+        }
+
+        let size = taker.remaining().min(maker.remaining());
+        let taker_margin = release_margin(taker, size);
+        let maker_margin = release_margin(&mut maker, size);
+        if maker.remaining().is_zero() {
+            orders().remove(storage, maker_order_id)?;
+        } else {
+            orders().save(storage, maker_order_id, &maker)?;
+        }
+
+        fills.push(Fill {
+            maker_order_id,
+            maker: maker.owner,
+            price: maker.price,
+            size,
+            maker_margin,
+            taker_margin,
+        });
+    }
+    Ok((fills, refunds))
+}
+
+/// What a market order for `size` on `side` would fill against the book.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MarketQuote {
+    pub size: Uint128,
+    /// Sum of each fill's size times its price.
+    pub cost: Uint128,
+    pub worst_price: Uint128,
+}
+
+impl MarketQuote {
+    pub fn average_price(&self) -> Uint128 {
+        self.cost / self.size
+    }
+}
+
+/// Walks the opposite side of a market's book for up to `size` without
+/// touching it. Orders of `taker`'s own wallet are skipped, as matching
+/// cancels them instead of filling them.
+pub fn quote_market_order(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    market_id: u64,
+    taker: &Addr,
+    side: Side,
+    size: Uint128,
+) -> StdResult<MarketQuote> {
+    let taker_wallet = account_owner(storage, taker)?;
+    let mut quote = MarketQuote::default();
+    for item in live_orders(storage, block, market_id, side.opposite()) {
+        if quote.size == size {
+            break;
+        }
+        let (_, order) = item?;
+        if account_owner(storage, &order.owner)? == taker_wallet {
+            continue;
+        }
+        let fill = order.remaining().min(size - quote.size);
+        quote.size += fill;
+        quote.cost += fill.checked_mul(order.price)?;
+        quote.worst_price = order.price;
+    }
+    Ok(quote)
+}
+
+/// Aggregates up to `depth` price levels of the live orders on one side of a
+/// market's book.
+pub fn price_levels(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    market_id: u64,
+    side: Side,
+    depth: usize,
+) -> StdResult<Vec<PriceLevel>> {
+    let mut levels: Vec<PriceLevel> = vec![];
+    for item in live_orders(storage, block, market_id, side) {
+        let (_, order) = item?;
+        match levels.last_mut() {
+            Some(level) if level.price == order.price => level.size += order.remaining(),
+            _ => {
+                if levels.len() == depth {
+                    break;
+                }
+                levels.push(PriceLevel {
+                    price: order.price,
+                    size: order.remaining(),
+                });
+            }
+        }
+    }
+    Ok(levels)
+}
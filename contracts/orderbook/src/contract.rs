@@ -1,148 +1,3811 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{
+    from_binary, to_binary, Addr, BankMsg, Binary, BlockInfo, Coin, CosmosMsg, Decimal, Deps,
+    DepsMut, Empty, Env, Event, Int128, MessageInfo, Order, QuerierWrapper, Reply, Response,
+    StdResult, Storage, SubMsg, SubMsgResult, Uint128, WasmMsg,
+};
 use cw2::set_contract_version;
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg, Expiration};
+use cw_storage_plus::Bound;
 
+use crate::accounts::{account_owner, sub_account_addr, validate_account};
+use crate::book::{
+    best_order, crosses, fillable_size, match_order, price_levels, quote_market_order,
+};
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, GetCountResponse, InstantiateMsg, QueryMsg};
-use crate::state::{State, STATE};
+use crate::fees::{fee_amount, fee_tier, pay_referral, record_volume, rolling_volume, MAX_FEE_BPS};
+use crate::msg::{
+    AccountFeesResponse, AdlRank, AdlRankingResponse, BatchOrdersResponse, CancelledOrder,
+    ConfigResponse, DelegatesResponse, ExecuteMsg, FeeTiersResponse, FundingResponse,
+    InstantiateMsg, InsuranceFundResponse, LastTradePriceResponse, LimitOrder,
+    LiquidatablePosition, LiquidatablePositionsResponse, ListMarketsResponse,
+    MarginAccountResponse, MarketResponse, MarketStatsResponse, OrderBookResponse, OrdersResponse,
+    PlacedOrder, PositionResponse, PositionsResponse, QueryMsg, ReceiveMsg,
+    ReferralRewardsResponse, ReferralStatsResponse, SubAccount, SubAccountsResponse, TimeInForce,
+    TradesResponse, TriggerOrdersResponse,
+};
+use crate::position::{
+    adl_ranking, apply_fill, average_entry, funding_index, initial_margin, leverage,
+    liquidation_summary, margin_mode, margin_summary, notional, pending_funding, pnl,
+    position_summary, query_mark_price, query_oracle_price, query_settlement_price, realize_pnl,
+    save_position, settle, settle_funding, signed_size, to_signed, unrealized_pnl, MarginSummary,
+    LIQUIDATION_BONUS_BPS, MAX_LEVERAGE, MIN_LIQUIDATION_BONUS_BPS,
+};
+use crate::state::{
+    orders, trigger_orders, Collateral, Config, FeeTier, MarginMode, Market, MarketStatus,
+    OpenInterest, Order as BookOrder, Position, Side, Trade, TriggerKind, TriggerOrder, ACCOUNTS,
+    ACCOUNT_OWNERS, ACCOUNT_REFERRAL_CODES, ACCOUNT_TRADES, ACCRUED_FEES, CONFIG, DELEGATES,
+    FEE_TIERS, FUNDING, INSURANCE_FUND, MARKETS, MARKET_COUNT, MARKET_TRADES, OPEN_INTEREST,
+    ORDER_COUNT, POSITIONS, PRICE_PRECISION, REFERRALS, REFERRAL_CODES, REFERRERS, SUB_ACCOUNTS,
+    SUB_ACCOUNT_COUNT, TRIGGER_COUNT,
+};
+use crate::trades::{fill_event, record_trade, trades_page};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:orderbook";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+const MAX_DEPTH: u32 = 50;
+// positions a liquidatable positions query reads, hits or not
+const MAX_LIQUIDATION_SCAN: usize = 100;
+const FUNDING_INTERVAL: u64 = 60 * 60;
+// slippage a triggered stop loss or take profit accepts from the mark price
+const TRIGGER_SLIPPAGE_BPS: u64 = 500;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    _env: Env,
+    _info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    if let Collateral::Cw20 { address } = &msg.collateral {
+        deps.api.addr_validate(address)?;
+    }
+    if msg.referral_share_bps > 10_000 {
+        return Err(ContractError::InvalidReferralShare {});
+    }
+    let config = Config {
+        owner: deps.api.addr_validate(&msg.owner)?,
+        oracle: deps.api.addr_validate(&msg.oracle)?,
+        collateral: msg.collateral,
+        treasury: deps.api.addr_validate(&msg.treasury)?,
+        trigger_fee: msg.trigger_fee,
+        max_batch_size: msg.max_batch_size,
+        referral_share_bps: msg.referral_share_bps,
+    };
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "instantiate")
+        .add_attribute("owner", msg.owner)
+        .add_attribute("oracle", msg.oracle))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::CreateMarket {
+            base_asset,
+            quote_asset,
+            max_bet_size,
+            tick_size,
+            min_order_size,
+            max_order_size,
+            max_open_interest,
+            max_funding_rate,
+            price_band_bps,
+            max_price_age,
+            expiry,
+        } => try_create_market(
+            deps,
+            env,
+            info,
+            base_asset,
+            quote_asset,
+            max_bet_size,
+            tick_size,
+            min_order_size,
+            max_order_size,
+            max_open_interest,
+            max_funding_rate,
+            price_band_bps,
+            max_price_age,
+            expiry,
+        ),
+        ExecuteMsg::UpdateMarket {
+            market_id,
+            max_bet_size,
+            tick_size,
+            min_order_size,
+            max_order_size,
+            max_open_interest,
+            max_funding_rate,
+            price_band_bps,
+            max_price_age,
+        } => try_update_market(
+            deps,
+            info,
+            market_id,
+            max_bet_size,
+            tick_size,
+            min_order_size,
+            max_order_size,
+            max_open_interest,
+            max_funding_rate,
+            price_band_bps,
+            max_price_age,
+        ),
+        ExecuteMsg::CloseMarket { market_id } => try_close_market(deps, info, market_id),
+        ExecuteMsg::HaltMarket { market_id } => try_halt_market(deps, info, market_id),
+        ExecuteMsg::ResumeMarket { market_id } => try_resume_market(deps, info, market_id),
+        ExecuteMsg::PlaceLimitOrder {
+            market_id,
+            side,
+            price,
+            size,
+            post_only,
+            expires,
+            reduce_only,
+        } => try_place_limit_order(
+            deps,
+            env,
+            info,
+            LimitOrder {
+                market_id,
+                side,
+                price,
+                size,
+                post_only,
+                expires,
+                reduce_only,
+            },
+        ),
+        ExecuteMsg::PlaceMarketOrder {
+            market_id,
+            side,
+            size,
+            max_slippage_bps,
+            time_in_force,
+            reduce_only,
+        } => try_place_market_order(
+            deps,
+            env,
+            info,
+            market_id,
+            side,
+            size,
+            max_slippage_bps,
+            time_in_force,
+            reduce_only,
+        ),
+        ExecuteMsg::CancelOrder { order_id } => try_cancel_order(deps, info, order_id),
+        ExecuteMsg::CancelAll { market_id } => try_cancel_all(deps, info, market_id),
+        ExecuteMsg::BatchOrders { cancels, places } => {
+            try_batch_orders(deps, env, info, cancels, places)
+        }
+        ExecuteMsg::ReplaceOrder {
+            order_id,
+            price,
+            size,
+            post_only,
+            expires,
+        } => try_replace_order(deps, env, info, order_id, price, size, post_only, expires),
+        ExecuteMsg::PruneExpired { market_id, limit } => {
+            try_prune_expired(deps, env, market_id, limit)
+        }
+        ExecuteMsg::OpenPosition {
+            market_id,
+            side,
+            size,
+            collateral,
+        } => try_open_position(deps, env, info, market_id, side, size, collateral),
+        ExecuteMsg::ClosePosition { market_id, size } => {
+            try_close_position(deps, env, info, market_id, size)
+        }
+        ExecuteMsg::SettlePosition { market_id } => try_settle_position(deps, env, info, market_id),
+        ExecuteMsg::Deposit { referral_code } => try_deposit(deps, info, referral_code),
+        ExecuteMsg::Withdraw { amount } => try_withdraw(deps, info, amount),
+        ExecuteMsg::Receive(msg) => try_receive(deps, info, msg),
+        ExecuteMsg::SettleFunding { market_id } => try_settle_funding(deps, env, market_id),
+        ExecuteMsg::Liquidate { owner, market_id } => {
+            try_liquidate(deps, env, info, owner, market_id)
+        }
+        ExecuteMsg::PlaceTriggerOrder {
+            market_id,
+            side,
+            kind,
+            trigger_price,
+            size,
+        } => try_place_trigger_order(deps, env, info, market_id, side, kind, trigger_price, size),
+        ExecuteMsg::CancelTriggerOrder { trigger_id } => {
+            try_cancel_trigger_order(deps, info, trigger_id)
+        }
+        ExecuteMsg::ExecuteTriggers { market_id, limit } => {
+            try_execute_triggers(deps, env, info, market_id, limit)
+        }
+        ExecuteMsg::ExecuteTrigger {
+            trigger_id,
+            keeper,
+            mark_price,
+        } => try_execute_trigger(deps, env, info, trigger_id, keeper, mark_price),
+        ExecuteMsg::SetMaxBatchSize { max_batch_size } => {
+            try_set_max_batch_size(deps, info, max_batch_size)
+        }
+        ExecuteMsg::SetReferralShare { referral_share_bps } => {
+            try_set_referral_share(deps, info, referral_share_bps)
+        }
+        ExecuteMsg::RegisterReferralCode { code } => try_register_referral_code(deps, info, code),
+        ExecuteMsg::ClaimReferralRewards {} => try_claim_referral_rewards(deps, info),
+        ExecuteMsg::UpdateFeeTiers { tiers } => try_update_fee_tiers(deps, info, tiers),
+        ExecuteMsg::SweepFees {} => try_sweep_fees(deps),
+        ExecuteMsg::FundInsurance {} => try_fund_insurance(deps, info),
+        ExecuteMsg::WithdrawInsurance { amount } => try_withdraw_insurance(deps, info, amount),
+        ExecuteMsg::SetMarginMode { mode } => try_set_margin_mode(deps, info, mode),
+        ExecuteMsg::AddCollateral { market_id, amount } => {
+            try_add_collateral(deps, info, market_id, amount)
+        }
+        ExecuteMsg::RemoveCollateral { market_id, amount } => {
+            try_remove_collateral(deps, env, info, market_id, amount)
+        }
+        ExecuteMsg::CreateSubAccount {} => try_create_sub_account(deps, info),
+        ExecuteMsg::AddDelegate {
+            sub_account,
+            delegate,
+        } => try_add_delegate(deps, info, sub_account, delegate),
+        ExecuteMsg::RemoveDelegate {
+            sub_account,
+            delegate,
+        } => try_remove_delegate(deps, info, sub_account, delegate),
+        ExecuteMsg::WithAccount {
+            owner,
+            sub_account,
+            msg,
+        } => try_with_account(deps, env, info, owner, sub_account, *msg),
+    }
+}
+
+pub(crate) fn load_market(storage: &dyn Storage, market_id: u64) -> Result<Market, ContractError> {
+    MARKETS
+        .may_load(storage, market_id)?
+        .ok_or(ContractError::MarketNotFound { market_id })
+}
+
+fn validate_market(market: &Market) -> Result<(), ContractError> {
+    let reason = if market.base_asset.is_empty() || market.quote_asset.is_empty() {
+        "oracle pair must not be empty"
+    } else if market.tick_size.is_zero() {
+        "tick size must be positive"
+    } else if market.min_order_size.is_zero() {
+        "min order size must be positive"
+    } else if market.max_bet_size < market.min_order_size {
+        "max bet size must be at least the min order size"
+    } else if market.max_order_size < market.min_order_size {
+        "max order size must be at least the min order size"
+    } else if market.max_open_interest < market.max_bet_size {
+        "max open interest must be at least the max bet size"
+    } else if market.max_funding_rate > Decimal::one() {
+        "max funding rate must be at most 100%"
+    } else if market
+        .price_band_bps
+        .is_some_and(|band| band == 0 || band > 10_000)
+    {
+        "price band must be between 1 and 10000 bps"
+    } else if market.max_price_age == Some(0) {
+        "max price age must be positive"
+    } else {
+        return Ok(());
+    };
+    Err(ContractError::InvalidMarketParams {
+        reason: reason.to_string(),
+    })
+}
+
+fn validate_order_size(market: &Market, size: Uint128) -> Result<(), ContractError> {
+    if size < market.min_order_size {
+        return Err(ContractError::OrderTooSmall {
+            min_order_size: market.min_order_size,
+        });
+    }
+    if size > market.max_order_size {
+        return Err(ContractError::OrderTooLarge {
+            max_order_size: market.max_order_size,
+        });
+    }
+    Ok(())
+}
+
+/// Rejects an order that would take the owner's position in the market past
+/// the max bet size if it and the owner's resting orders on the same side
+/// all filled completely. Reduce-only orders never grow the position and are
+/// left out.
+fn validate_position_size(
+    storage: &dyn Storage,
+    market: &Market,
+    owner: &Addr,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+) -> Result<(), ContractError> {
+    let current = POSITIONS
+        .may_load(storage, (owner, market_id))?
+        .map(|position| position.size)
+        .unwrap_or_default();
+    let resting = orders()
+        .idx
+        .owner
+        .prefix(owner.clone())
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| match item {
+            Ok((_, order)) => {
+                order.market_id == market_id && order.side == side && !order.reduce_only
+            }
+            Err(_) => true,
+        })
+        .map(|item| item.map(|(_, order)| order.remaining()))
+        .sum::<StdResult<Uint128>>()?;
+    let size = current.checked_add(signed_size(side, resting + size)?)?;
+    if size.i128().unsigned_abs() > market.max_bet_size.u128() {
+        return Err(ContractError::PositionTooLarge {
+            max_bet_size: market.max_bet_size,
+        });
+    }
+    Ok(())
+}
+
+/// Rejects a trade that grew either side's open interest past the market's
+/// cap. Trades that only shrink open interest always go through.
+fn validate_open_interest(
+    storage: &dyn Storage,
+    market: &Market,
+    market_id: u64,
+    before: &OpenInterest,
+) -> Result<(), ContractError> {
+    let after = OPEN_INTEREST
+        .may_load(storage, market_id)?
+        .unwrap_or_default();
+    if (after.long > before.long && after.long > market.max_open_interest)
+        || (after.short > before.short && after.short > market.max_open_interest)
+    {
+        return Err(ContractError::OpenInterestCapExceeded {
+            max_open_interest: market.max_open_interest,
+        });
+    }
+    Ok(())
+}
+
+/// Trading address of an owner's sub-account, checking that it exists.
+fn load_sub_account(
+    storage: &dyn Storage,
+    owner: &Addr,
+    sub_account: u32,
+) -> Result<Addr, ContractError> {
+    if sub_account == 0 {
+        return Ok(owner.clone());
+    }
+    SUB_ACCOUNTS
+        .may_load(storage, (owner, sub_account))?
+        .ok_or(ContractError::SubAccountNotFound { sub_account })
+}
+
+/// Mark price of a market, refusing an oracle price older than the market's
+/// max price age.
+fn fresh_mark_price(
+    querier: &QuerierWrapper,
+    oracle: &Addr,
+    market: &Market,
+    now: u64,
+) -> Result<Uint128, ContractError> {
+    let price = query_oracle_price(querier, oracle, market)?;
+    if let Some(max_price_age) = market.max_price_age {
+        if now.saturating_sub(price.timestamp) > max_price_age {
+            return Err(ContractError::StalePrice {
+                updated_at: price.timestamp,
+                max_price_age,
+            });
+        }
+    }
+    Ok(price.amount)
+}
+
+/// Rejects a limit price outside the market's band around a fresh mark
+/// price. Markets without a band or max price age skip the oracle.
+fn validate_price_band(
+    querier: &QuerierWrapper,
+    oracle: &Addr,
+    market: &Market,
+    price: Uint128,
+    now: u64,
+) -> Result<(), ContractError> {
+    if market.price_band_bps.is_none() && market.max_price_age.is_none() {
+        return Ok(());
+    }
+    let mark_price = fresh_mark_price(querier, oracle, market, now)?;
+    if let Some(band_bps) = market.price_band_bps {
+        let width = mark_price.multiply_ratio(band_bps, 10_000u64);
+        let min_price = mark_price.saturating_sub(width);
+        let max_price = mark_price + width;
+        if price < min_price || price > max_price {
+            return Err(ContractError::PriceOutsideBand {
+                price,
+                mark_price,
+                min_price,
+                max_price,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Rejects trading in a market that is closed, halted or past its expiry.
+fn ensure_trading(market: &Market, market_id: u64, now: u64) -> Result<(), ContractError> {
+    ensure_not_frozen(market, market_id, now)?;
+    if market.status == MarketStatus::Closed {
+        return Err(ContractError::MarketClosed { market_id });
+    }
+    Ok(())
+}
+
+/// Rejects closing and liquidating positions in a halted or expired market.
+/// Closed markets still let positions wind down.
+fn ensure_not_frozen(market: &Market, market_id: u64, now: u64) -> Result<(), ContractError> {
+    if market.status == MarketStatus::Halted {
+        return Err(ContractError::MarketHalted { market_id });
+    }
+    if market.is_expired(now) {
+        return Err(ContractError::MarketExpired { market_id });
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_create_market(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    base_asset: String,
+    quote_asset: String,
+    max_bet_size: Uint128,
+    tick_size: Uint128,
+    min_order_size: Uint128,
+    max_order_size: Uint128,
+    max_open_interest: Uint128,
+    max_funding_rate: Decimal,
+    price_band_bps: Option<u64>,
+    max_price_age: Option<u64>,
+    expiry: Option<u64>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if expiry.is_some_and(|expiry| expiry <= env.block.time.seconds()) {
+        return Err(ContractError::InvalidMarketParams {
+            reason: "expiry must be in the future".to_string(),
+        });
+    }
+
+    let market = Market {
+        base_asset,
+        quote_asset,
+        max_bet_size,
+        tick_size,
+        min_order_size,
+        max_order_size,
+        max_open_interest,
+        max_funding_rate,
+        price_band_bps,
+        max_price_age,
+        expiry,
+        settlement_price: None,
+        owner: info.sender,
+        status: MarketStatus::Active,
+    };
+    validate_market(&market)?;
+
+    let market_id = MARKET_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    MARKETS.save(deps.storage, market_id, &market)?;
+    MARKET_COUNT.save(deps.storage, &(market_id + 1))?;
+
+    Ok(Response::new()
+        .add_attribute("method", "create_market")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("base_asset", market.base_asset)
+        .add_attribute("quote_asset", market.quote_asset))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_update_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: u64,
+    max_bet_size: Option<Uint128>,
+    tick_size: Option<Uint128>,
+    min_order_size: Option<Uint128>,
+    max_order_size: Option<Uint128>,
+    max_open_interest: Option<Uint128>,
+    max_funding_rate: Option<Decimal>,
+    price_band_bps: Option<u64>,
+    max_price_age: Option<u64>,
+) -> Result<Response, ContractError> {
+    let mut market = load_market(deps.storage, market_id)?;
+    if info.sender != market.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if market.status == MarketStatus::Closed {
+        return Err(ContractError::MarketClosed { market_id });
+    }
+
+    if let Some(max_bet_size) = max_bet_size {
+        market.max_bet_size = max_bet_size;
+    }
+    if let Some(tick_size) = tick_size {
+        market.tick_size = tick_size;
+    }
+    if let Some(min_order_size) = min_order_size {
+        market.min_order_size = min_order_size;
+    }
+    if let Some(max_order_size) = max_order_size {
+        market.max_order_size = max_order_size;
+    }
+    if let Some(max_open_interest) = max_open_interest {
+        market.max_open_interest = max_open_interest;
+    }
+    if let Some(max_funding_rate) = max_funding_rate {
+        market.max_funding_rate = max_funding_rate;
+    }
+    // zero clears the band or the staleness limit
+    if let Some(price_band_bps) = price_band_bps {
+        market.price_band_bps = Some(price_band_bps).filter(|bps| *bps != 0);
+    }
+    if let Some(max_price_age) = max_price_age {
+        market.max_price_age = Some(max_price_age).filter(|age| *age != 0);
+    }
+    validate_market(&market)?;
+    MARKETS.save(deps.storage, market_id, &market)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "update_market")
+        .add_attribute("market_id", market_id.to_string()))
+}
+
+pub fn try_close_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let mut market = load_market(deps.storage, market_id)?;
+    if info.sender != market.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if market.status == MarketStatus::Closed {
+        return Err(ContractError::MarketClosed { market_id });
+    }
+    market.status = MarketStatus::Closed;
+    MARKETS.save(deps.storage, market_id, &market)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "close_market")
+        .add_attribute("market_id", market_id.to_string()))
+}
+
+pub fn try_halt_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let mut market = load_market(deps.storage, market_id)?;
+    if info.sender != market.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    match market.status {
+        MarketStatus::Active => {}
+        MarketStatus::Halted => return Err(ContractError::MarketHalted { market_id }),
+        MarketStatus::Closed => return Err(ContractError::MarketClosed { market_id }),
+    }
+    market.status = MarketStatus::Halted;
+    MARKETS.save(deps.storage, market_id, &market)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "halt_market")
+        .add_attribute("market_id", market_id.to_string()))
+}
+
+pub fn try_resume_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let mut market = load_market(deps.storage, market_id)?;
+    if info.sender != market.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if market.status != MarketStatus::Halted {
+        return Err(ContractError::MarketNotHalted { market_id });
+    }
+    market.status = MarketStatus::Active;
+    MARKETS.save(deps.storage, market_id, &market)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "resume_market")
+        .add_attribute("market_id", market_id.to_string()))
+}
+
+/// A placed order's id and what is left of it, with the trades it made and
+/// the events of any auto-deleveraging their losses caused.
+type Placement = (u64, BookOrder, Vec<(u64, Trade)>, Vec<Event>);
+
+/// Places `order` for `owner`, resting whatever does not match.
+fn place_limit_order(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    block: &BlockInfo,
+    owner: &Addr,
+    order: &LimitOrder,
+) -> Result<Placement, ContractError> {
+    let now = block.time.seconds();
+    let market_id = order.market_id;
+    let config = CONFIG.load(storage)?;
+    let market = load_market(storage, market_id)?;
+    ensure_trading(&market, market_id, now)?;
+    validate_price_band(querier, &config.oracle, &market, order.price, now)?;
+    let book_order = BookOrder {
+        market_id,
+        owner: owner.clone(),
+        side: order.side,
+        price: order.price,
+        size: order.size,
+        filled: Uint128::zero(),
+        created_at: now,
+        margin: Uint128::zero(),
+        expires: order.expires.unwrap_or_default(),
+        reduce_only: order.reduce_only,
+    };
+    if book_order.expires.is_expired(block) {
+        return Err(ContractError::InvalidExpiry {});
+    }
+    if order.post_only {
+        if let Some((_, best)) = best_order(storage, block, market_id, order.side.opposite())? {
+            if crosses(&book_order, best.price) {
+                return Err(ContractError::PostOnlyWouldCross {
+                    best_price: best.price,
+                });
+            }
+        }
+    }
+    place_order(storage, block, &market, book_order, true)
+}
+
+pub fn try_place_limit_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order: LimitOrder,
+) -> Result<Response, ContractError> {
+    let (order_id, order, trades, adl_events) = place_limit_order(
+        deps.storage,
+        &deps.querier,
+        &env.block,
+        &info.sender,
+        &order,
+    )?;
+
+    Ok(Response::new()
+        .add_events(trades.iter().map(|(id, trade)| fill_event(*id, trade)))
+        .add_events(adl_events)
+        .add_attribute("method", "place_limit_order")
+        .add_attribute("order_id", order_id.to_string())
+        .add_attribute("fills", trades.len().to_string())
+        .add_attribute("filled", order.filled)
+        .add_attribute("remaining", order.remaining()))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_place_market_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+    max_slippage_bps: u64,
+    time_in_force: TimeInForce,
+    reduce_only: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_trading(&market, market_id, env.block.time.seconds())?;
+    validate_order_size(&market, size)?;
+    let size = match reduce_only {
+        true => reduce_only_size(deps.storage, &info.sender, market_id, side, size)?,
+        false => size,
+    };
+
+    // price the fills against the book up front, so that the order can be
+    // placed as a limit at the worst price it reaches
+    let quote = quote_market_order(
+        deps.storage,
+        &env.block,
+        market_id,
+        &info.sender,
+        side,
+        size,
+    )?;
+    if quote.size.is_zero() || (time_in_force == TimeInForce::Fok && quote.size < size) {
+        return Err(ContractError::InsufficientLiquidity {
+            available: quote.size,
+        });
+    }
+    let mark_price = fresh_mark_price(
+        &deps.querier,
+        &config.oracle,
+        &market,
+        env.block.time.seconds(),
+    )?;
+    let average_price = quote.average_price();
+    let max_deviation = mark_price.multiply_ratio(max_slippage_bps, 10_000u64);
+    if average_price.abs_diff(mark_price) > max_deviation {
+        return Err(ContractError::SlippageTooHigh {
+            average_price,
+            mark_price,
+        });
+    }
+
+    let order = BookOrder {
+        market_id,
+        owner: info.sender,
+        side,
+        price: quote.worst_price,
+        size: quote.size,
+        filled: Uint128::zero(),
+        created_at: env.block.time.seconds(),
+        margin: Uint128::zero(),
+        expires: Expiration::Never {},
+        reduce_only,
+    };
+    let (order_id, order, trades, adl_events) =
+        place_order(deps.storage, &env.block, &market, order, false)?;
+    // resting reduce-only orders clamped while matching can leave the quote
+    // short
+    if time_in_force == TimeInForce::Fok && order.filled < size {
+        return Err(ContractError::InsufficientLiquidity {
+            available: order.filled,
+        });
+    }
+
+    Ok(Response::new()
+        .add_events(trades.iter().map(|(id, trade)| fill_event(*id, trade)))
+        .add_events(adl_events)
+        .add_attribute("method", "place_market_order")
+        .add_attribute("order_id", order_id.to_string())
+        .add_attribute("fills", trades.len().to_string())
+        .add_attribute("filled", order.filled)
+        .add_attribute("average_price", average_price)
+        .add_attribute("cancelled", size - order.filled))
+}
+
+/// Clamps a reduce-only order for `size` to what is open on the other side of
+/// `owner`'s position, failing if nothing is.
+fn reduce_only_size(
+    storage: &dyn Storage,
+    owner: &Addr,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+) -> Result<Uint128, ContractError> {
+    let reducible = fillable_size(
+        storage,
+        owner,
+        market_id,
+        side,
+        Int128::zero(),
+        true,
+        Uint128::zero(),
+    )?;
+    if reducible.is_zero() {
+        return Err(ContractError::NothingToReduce { market_id });
+    }
+    Ok(size.min(reducible))
+}
+
+/// Reserves initial margin for a new order, matches it against the book and
+/// applies the fills to both sides' positions. Whatever is left rests on the
+/// book, or with `rest` unset has its margin released back to the owner.
+/// Reduce-only orders are first clamped to the owner's opposite position.
+fn place_order(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    market: &Market,
+    mut order: BookOrder,
+    rest: bool,
+) -> Result<Placement, ContractError> {
+    let market_id = order.market_id;
+    if order.price.is_zero() || !(order.price % market.tick_size).is_zero() {
+        return Err(ContractError::InvalidPrice {
+            price: order.price,
+            tick_size: market.tick_size,
+        });
+    }
+    validate_order_size(market, order.size)?;
+    if order.reduce_only {
+        order.size = reduce_only_size(storage, &order.owner, market_id, order.side, order.size)?;
+    } else {
+        validate_position_size(
+            storage,
+            market,
+            &order.owner,
+            market_id,
+            order.side,
+            order.size,
+        )?;
+    }
+
+    order.margin = initial_margin(order.size, order.price);
+    debit_account(storage, &order.owner, order.margin)?;
+
+    let order_id = ORDER_COUNT.may_load(storage)?.unwrap_or_default();
+    ORDER_COUNT.save(storage, &(order_id + 1))?;
+    let open_interest = OPEN_INTEREST
+        .may_load(storage, market_id)?
+        .unwrap_or_default();
+    let now = order.created_at;
+    let (fills, refunds) = match_order(storage, block, &mut order, market.max_bet_size)?;
+    for (owner, refund) in refunds {
+        credit_account(storage, &owner, refund)?;
+    }
+    let tiers = FEE_TIERS.may_load(storage)?.unwrap_or_default();
+    let referral_share_bps = CONFIG.load(storage)?.referral_share_bps;
+    let (_, taker_tier) = fee_tier(&tiers, rolling_volume(storage, &order.owner, now)?);
+    let mut accrued_fees = ACCRUED_FEES.may_load(storage)?.unwrap_or_default();
+    let mut trades = vec![];
+    let mut adl_events = vec![];
+    for fill in fills {
+        // fees come out of the margin each side posts for the fill, which
+        // always covers them as fees are capped below the initial margin.
+        // Maker rebates go straight to the maker's margin account, and the
+        // taker's referrer is paid from what is left of the taker fee.
+        // Rebates never exceed the taker fee, as validated with the tiers.
+        let notional = notional(fill.size, fill.price);
+        let (_, maker_tier) = fee_tier(&tiers, rolling_volume(storage, &fill.maker, now)?);
+        let maker_fee = fee_amount(notional, maker_tier.maker_fee_bps.unsigned_abs());
+        let taker_fee = fee_amount(notional, taker_tier.taker_fee_bps);
+        let mut maker_margin = fill.maker_margin;
+        accrued_fees += taker_fee;
+        let signed_maker_fee = if maker_tier.maker_fee_bps < 0 {
+            credit_account(storage, &fill.maker, maker_fee)?;
+            accrued_fees -= maker_fee;
+            -to_signed(maker_fee)?
+        } else {
+            maker_margin = maker_margin.checked_sub(maker_fee)?;
+            accrued_fees += maker_fee;
+            to_signed(maker_fee)?
+        };
+        let rebate = match maker_tier.maker_fee_bps < 0 {
+            true => maker_fee,
+            false => Uint128::zero(),
+        };
+        accrued_fees -= pay_referral(
+            storage,
+            &order.owner,
+            notional,
+            taker_fee,
+            taker_fee - rebate,
+            referral_share_bps,
+        )?;
+        record_volume(storage, &fill.maker, now, notional)?;
+        record_volume(storage, &order.owner, now, notional)?;
+
+        // a side that closes at a loss beyond its collateral is covered like
+        // a liquidation, the position it closed being on the fill's other side
+        for (owner, side, margin) in [
+            (&fill.maker, order.side.opposite(), maker_margin),
+            (
+                &order.owner,
+                order.side,
+                fill.taker_margin.checked_sub(taker_fee)?,
+            ),
+        ] {
+            let (closed, shortfall) = apply_fill(
+                storage, now, owner, market_id, side, fill.size, fill.price, margin,
+            )?;
+            if !shortfall.is_zero() {
+                let (_, events) = cover_shortfall(
+                    storage,
+                    market_id,
+                    side.opposite(),
+                    closed,
+                    fill.price,
+                    shortfall,
+                )?;
+                adl_events.extend(events);
+            }
+        }
+
+        let trade = Trade {
+            market_id,
+            maker: fill.maker,
+            taker: order.owner.clone(),
+            maker_order_id: fill.maker_order_id,
+            taker_order_id: order_id,
+            side: order.side,
+            price: fill.price,
+            size: fill.size,
+            maker_fee: signed_maker_fee,
+            taker_fee,
+            height: block.height,
+            timestamp: now,
+        };
+        trades.push((record_trade(storage, &trade)?, trade));
+    }
+    ACCRUED_FEES.save(storage, &accrued_fees)?;
+    validate_open_interest(storage, market, market_id, &open_interest)?;
+    if !order.remaining().is_zero() {
+        if rest {
+            orders().save(storage, order_id, &order)?;
+        } else {
+            credit_account(storage, &order.owner, std::mem::take(&mut order.margin))?;
+        }
+    }
+    Ok((order_id, order, trades, adl_events))
+}
+
+/// Worst price a market order on `side` accepts, `slippage_bps` away from the
+/// mark price and rounded towards it to the market's tick size.
+fn slippage_price(market: &Market, side: Side, mark_price: Uint128, slippage_bps: u64) -> Uint128 {
+    let tick = market.tick_size;
+    let price = match side {
+        Side::Buy => {
+            let price = mark_price.multiply_ratio(10_000 + slippage_bps, 10_000u64);
+            price - price % tick
+        }
+        Side::Sell => {
+            let price =
+                mark_price.multiply_ratio(10_000u64.saturating_sub(slippage_bps), 10_000u64);
+            match price % tick {
+                rem if rem.is_zero() => price,
+                rem => price + tick - rem,
+            }
+        }
+    };
+    price.max(tick)
+}
+
+/// Removes one of `owner`'s resting orders and refunds its reserved margin.
+fn cancel_order(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    order_id: u64,
+) -> Result<BookOrder, ContractError> {
+    let order = orders()
+        .may_load(storage, order_id)?
+        .ok_or(ContractError::OrderNotFound { order_id })?;
+    if *owner != order.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    orders().remove(storage, order_id)?;
+    credit_account(storage, &order.owner, order.margin)?;
+    Ok(order)
+}
+
+pub fn try_cancel_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    order_id: u64,
+) -> Result<Response, ContractError> {
+    cancel_order(deps.storage, &info.sender, order_id)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "cancel_order")
+        .add_attribute("order_id", order_id.to_string()))
+}
+
+pub fn try_cancel_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: Option<u64>,
+) -> Result<Response, ContractError> {
+    let cancelled = orders()
+        .idx
+        .owner
+        .prefix(info.sender.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| match (item, market_id) {
+            (Ok((_, order)), Some(market_id)) => order.market_id == market_id,
+            _ => true,
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut refund = Uint128::zero();
+    for (order_id, order) in &cancelled {
+        orders().remove(deps.storage, *order_id)?;
+        refund += order.margin;
+    }
+    credit_account(deps.storage, &info.sender, refund)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "cancel_all")
+        .add_attribute("cancelled", cancelled.len().to_string()))
+}
+
+pub fn try_batch_orders(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cancels: Vec<u64>,
+    places: Vec<LimitOrder>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if cancels.len() + places.len() > config.max_batch_size as usize {
+        return Err(ContractError::BatchTooLarge {
+            max_batch_size: config.max_batch_size,
+        });
+    }
+
+    let mut cancelled = vec![];
+    for order_id in cancels {
+        let order = cancel_order(deps.storage, &info.sender, order_id)?;
+        cancelled.push(CancelledOrder {
+            order_id,
+            refund: order.margin,
+        });
+    }
+    let mut placed = vec![];
+    let mut events = vec![];
+    for order in &places {
+        let (order_id, order, trades, adl_events) =
+            place_limit_order(deps.storage, &deps.querier, &env.block, &info.sender, order)?;
+        events.extend(trades.iter().map(|(id, trade)| fill_event(*id, trade)));
+        events.extend(adl_events);
+        placed.push(PlacedOrder {
+            order_id,
+            filled: order.filled,
+            remaining: order.remaining(),
+        });
+    }
+
+    Ok(Response::new()
+        .add_events(events)
+        .add_attribute("method", "batch_orders")
+        .add_attribute("cancelled", cancelled.len().to_string())
+        .add_attribute("placed", placed.len().to_string())
+        .set_data(to_binary(&BatchOrdersResponse { cancelled, placed })?))
+}
+
+pub fn try_prune_expired(
+    deps: DepsMut,
+    env: Env,
+    market_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let mut expired = vec![];
+    // orders expiring at a height, then at a time, earliest first, so the
+    // scan stops at the first order that has not expired yet
+    for kind in [0, 1] {
+        expired.extend(
+            orders()
+                .idx
+                .expiry
+                .sub_prefix((market_id, kind))
+                .range(deps.storage, None, None, Order::Ascending)
+                .take_while(|item| match item {
+                    Ok((_, order)) => order.expires.is_expired(&env.block),
+                    Err(_) => true,
+                })
+                .take(limit - expired.len())
+                .collect::<StdResult<Vec<_>>>()?,
+        );
+    }
+    let mut refunded = Uint128::zero();
+    for (order_id, order) in &expired {
+        orders().remove(deps.storage, *order_id)?;
+        credit_account(deps.storage, &order.owner, order.margin)?;
+        refunded += order.margin;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "prune_expired")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("pruned", expired.len().to_string())
+        .add_attribute("refunded", refunded))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_replace_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order_id: u64,
+    price: Uint128,
+    size: Uint128,
+    post_only: bool,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let old = cancel_order(deps.storage, &info.sender, order_id)?;
+    let order = LimitOrder {
+        market_id: old.market_id,
+        side: old.side,
+        price,
+        size,
+        post_only,
+        expires,
+        reduce_only: old.reduce_only,
+    };
+    let (new_order_id, order, trades, adl_events) = place_limit_order(
+        deps.storage,
+        &deps.querier,
+        &env.block,
+        &info.sender,
+        &order,
+    )?;
+
+    Ok(Response::new()
+        .add_events(trades.iter().map(|(id, trade)| fill_event(*id, trade)))
+        .add_events(adl_events)
+        .add_attribute("method", "replace_order")
+        .add_attribute("replaced", order_id.to_string())
+        .add_attribute("order_id", new_order_id.to_string())
+        .add_attribute("fills", trades.len().to_string())
+        .set_data(to_binary(&PlacedOrder {
+            order_id: new_order_id,
+            filled: order.filled,
+            remaining: order.remaining(),
+        })?))
+}
+
+fn collateral_sent(info: &MessageInfo, collateral: &Collateral) -> Result<Uint128, ContractError> {
+    match (info.funds.as_slice(), collateral) {
+        ([coin], Collateral::Native { denom })
+            if &coin.denom == denom && !coin.amount.is_zero() =>
+        {
+            Ok(coin.amount)
+        }
+        _ => Err(ContractError::InvalidCollateral {}),
+    }
+}
+
+fn send_collateral(collateral: &Collateral, to: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+    Ok(match collateral {
+        Collateral::Native { denom } => BankMsg::Send {
+            to_address: to.to_string(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }
+        .into(),
+        Collateral::Cw20 { address } => WasmMsg::Execute {
+            contract_addr: address.clone(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: to.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        }
+        .into(),
+    })
+}
+
+fn credit_account(storage: &mut dyn Storage, owner: &Addr, amount: Uint128) -> StdResult<()> {
+    let mut account = ACCOUNTS.may_load(storage, owner)?.unwrap_or_default();
+    account.collateral += amount;
+    ACCOUNTS.save(storage, owner, &account)
+}
+
+fn debit_account(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let mut account = ACCOUNTS.may_load(storage, owner)?.unwrap_or_default();
+    if amount > account.collateral {
+        return Err(ContractError::InsufficientMargin {
+            available: account.collateral,
+        });
+    }
+    account.collateral -= amount;
+    ACCOUNTS.save(storage, owner, &account)?;
+    Ok(())
+}
+
+pub fn try_open_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+    collateral: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_trading(&market, market_id, env.block.time.seconds())?;
+    validate_order_size(&market, size)?;
+    validate_position_size(deps.storage, &market, &info.sender, market_id, side, size)?;
+    let mark_price = fresh_mark_price(
+        &deps.querier,
+        &config.oracle,
+        &market,
+        env.block.time.seconds(),
+    )?;
+
+    let key = (&info.sender, market_id);
+    let funding_index = funding_index(deps.storage, market_id)?;
+    let existing = POSITIONS.may_load(deps.storage, key)?;
+    let old_size = existing
+        .as_ref()
+        .map(|position| position.size)
+        .unwrap_or_default();
+    let position = match existing {
+        Some(position) if position.side() != side => {
+            return Err(ContractError::OppositePosition { market_id })
+        }
+        Some(mut position) => {
+            settle_funding(&mut position, funding_index)?;
+            position.entry_price =
+                average_entry(position.abs_size(), position.entry_price, size, mark_price)?;
+            position.size += signed_size(side, size)?;
+            position.collateral += collateral;
+            position
+        }
+        None => Position {
+            size: signed_size(side, size)?,
+            entry_price: mark_price,
+            collateral,
+            opened_at: env.block.time.seconds(),
+            funding_index,
+        },
+    };
+    let max_notional = position
+        .collateral
+        .checked_mul(Uint128::from(MAX_LEVERAGE))?;
+    if notional(position.abs_size(), position.entry_price) > max_notional {
+        return Err(ContractError::LeverageTooHigh {
+            max_leverage: MAX_LEVERAGE,
+        });
+    }
+    debit_account(deps.storage, &info.sender, collateral)?;
+    let open_interest = OPEN_INTEREST
+        .may_load(deps.storage, market_id)?
+        .unwrap_or_default();
+    save_position(deps.storage, &info.sender, market_id, old_size, &position)?;
+    validate_open_interest(deps.storage, &market, market_id, &open_interest)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "open_position")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("size", position.size.to_string())
+        .add_attribute("entry_price", position.entry_price)
+        .add_attribute("collateral", position.collateral))
+}
+
+pub fn try_close_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    size: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_not_frozen(&market, market_id, env.block.time.seconds())?;
+    let key = (&info.sender, market_id);
+    let mut position = POSITIONS
+        .may_load(deps.storage, key)?
+        .ok_or(ContractError::PositionNotFound { market_id })?;
+    settle_funding(&mut position, funding_index(deps.storage, market_id)?)?;
+    let mark_price = query_mark_price(&deps.querier, &config.oracle, &market)?;
+
+    let open_size = position.abs_size();
+    let size = size.unwrap_or(open_size);
+    if size > open_size {
+        return Err(ContractError::CloseTooLarge { size: open_size });
+    }
+    let side = position.side();
+    let closed = signed_size(side, size)?;
+    let realized_pnl = pnl(closed, position.entry_price, mark_price)?;
+    let released = position.collateral.multiply_ratio(size, open_size);
+
+    let old_size = position.size;
+    position.size -= closed;
+    position.collateral -= released;
+    save_position(deps.storage, &info.sender, market_id, old_size, &position)?;
+    let shortfall = realize_pnl(deps.storage, &info.sender, released, realized_pnl)?;
+    let (bad_debt, events) =
+        cover_shortfall(deps.storage, market_id, side, size, mark_price, shortfall)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "close_position")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("closed", size)
+        .add_attribute("realized_pnl", realized_pnl.to_string())
+        .add_attribute("shortfall", shortfall)
+        .add_attribute("bad_debt", bad_debt)
+        .add_events(events))
+}
+
+pub fn try_settle_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut market = load_market(deps.storage, market_id)?;
+    let expiry = match market.expiry {
+        Some(expiry) if market.is_expired(env.block.time.seconds()) => expiry,
+        Some(expiry) => return Err(ContractError::MarketNotExpired { market_id, expiry }),
+        None => {
+            return Err(ContractError::MarketNotExpired {
+                market_id,
+                expiry: 0,
+            })
+        }
+    };
+    // the first settlement fixes the price for everyone else
+    let settlement_price = match market.settlement_price {
+        Some(price) => price,
+        None => {
+            let price = query_settlement_price(&deps.querier, &config.oracle, &market, expiry)?;
+            market.settlement_price = Some(price);
+            MARKETS.save(deps.storage, market_id, &market)?;
+            price
+        }
+    };
+
+    let mut position = POSITIONS
+        .may_load(deps.storage, (&info.sender, market_id))?
+        .ok_or(ContractError::PositionNotFound { market_id })?;
+    settle_funding(&mut position, funding_index(deps.storage, market_id)?)?;
+    let realized_pnl = unrealized_pnl(&position, settlement_price)?;
+    let (side, size, released) = (position.side(), position.abs_size(), position.collateral);
+    let old_size = position.size;
+    position.size = Int128::zero();
+    position.collateral = Uint128::zero();
+    save_position(deps.storage, &info.sender, market_id, old_size, &position)?;
+    let shortfall = realize_pnl(deps.storage, &info.sender, released, realized_pnl)?;
+    let (bad_debt, events) = cover_shortfall(
+        deps.storage,
+        market_id,
+        side,
+        size,
+        settlement_price,
+        shortfall,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "settle_position")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("settlement_price", settlement_price)
+        .add_attribute("realized_pnl", realized_pnl.to_string())
+        .add_attribute("shortfall", shortfall)
+        .add_attribute("bad_debt", bad_debt)
+        .add_events(events))
+}
+
+/// Binds an account making its first deposit to the owner of `code`. Later
+/// deposits leave the referrer as it is.
+fn bind_referrer(
+    storage: &mut dyn Storage,
+    account: &Addr,
+    code: Option<String>,
+) -> Result<Option<Addr>, ContractError> {
+    let code = match code {
+        Some(code) if !ACCOUNTS.has(storage, account) && !REFERRERS.has(storage, account) => code,
+        _ => return Ok(None),
+    };
+    let referrer = REFERRAL_CODES
+        .may_load(storage, &code)?
+        .ok_or(ContractError::ReferralCodeNotFound { code })?;
+    if referrer == account_owner(storage, account)? {
+        return Err(ContractError::SelfReferral {});
+    }
+    REFERRERS.save(storage, account, &referrer)?;
+    let mut referrals = REFERRALS.may_load(storage, &referrer)?.unwrap_or_default();
+    referrals.referred_accounts += 1;
+    REFERRALS.save(storage, &referrer, &referrals)?;
+    Ok(Some(referrer))
+}
+
+pub fn try_deposit(
+    deps: DepsMut,
+    info: MessageInfo,
+    referral_code: Option<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let amount = collateral_sent(&info, &config.collateral)?;
+    let referrer = bind_referrer(deps.storage, &info.sender, referral_code)?;
+    credit_account(deps.storage, &info.sender, amount)?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "deposit")
+        .add_attribute("owner", info.sender)
+        .add_attribute("amount", amount);
+    if let Some(referrer) = referrer {
+        res = res.add_attribute("referrer", referrer);
+    }
+    Ok(res)
+}
+
+pub fn try_receive(
+    deps: DepsMut,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    match &config.collateral {
+        Collateral::Cw20 { address } if info.sender == *address => {}
+        _ => return Err(ContractError::InvalidCollateral {}),
+    }
+    if wrapper.amount.is_zero() {
+        return Err(ContractError::InvalidCollateral {});
+    }
+
+    let sender = deps.api.addr_validate(&wrapper.sender)?;
+    let (owner, referrer) = match from_binary(&wrapper.msg)? {
+        ReceiveMsg::Deposit {
+            sub_account,
+            referral_code,
+        } => {
+            let owner = load_sub_account(deps.storage, &sender, sub_account.unwrap_or_default())?;
+            let referrer = bind_referrer(deps.storage, &owner, referral_code)?;
+            (owner, referrer)
+        }
+        ReceiveMsg::FundInsurance {} => return fund_insurance(deps.storage, wrapper.amount),
+    };
+    credit_account(deps.storage, &owner, wrapper.amount)?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "deposit")
+        .add_attribute("owner", owner)
+        .add_attribute("amount", wrapper.amount);
+    if let Some(referrer) = referrer {
+        res = res.add_attribute("referrer", referrer);
+    }
+    Ok(res)
+}
+
+pub fn try_withdraw(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    // isolated positions do not draw on free collateral, so all of it can go
+    if margin_mode(deps.storage, &info.sender)? == MarginMode::Cross {
+        let summary = margin_summary(deps.as_ref(), &config.oracle, &info.sender)?;
+        if summary.equity()?.saturating_sub(amount) < summary.maintenance_margin {
+            return Err(ContractError::BelowMaintenanceMargin {
+                maintenance_margin: summary.maintenance_margin,
+            });
+        }
+    }
+    debit_account(deps.storage, &info.sender, amount)?;
+    let recipient = account_owner(deps.storage, &info.sender)?;
+
+    Ok(Response::new()
+        .add_message(send_collateral(&config.collateral, &recipient, amount)?)
+        .add_attribute("method", "withdraw")
+        .add_attribute("owner", info.sender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_settle_funding(
+    deps: DepsMut,
+    env: Env,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_trading(&market, market_id, env.block.time.seconds())?;
+    let mut funding = FUNDING
+        .may_load(deps.storage, market_id)?
+        .unwrap_or_default();
+    let now = env.block.time.seconds();
+    let next_funding = funding.last_settled + FUNDING_INTERVAL;
+    if now < next_funding {
+        return Err(ContractError::FundingNotDue { next_funding });
+    }
+
+    // the book's mid price is pulled toward the oracle index by charging the
+    // premium between them, within the market's cap, to the side it favors
+    let index_price = query_mark_price(&deps.querier, &config.oracle, &market)?;
+    let bid = best_order(deps.storage, &env.block, market_id, Side::Buy)?;
+    let ask = best_order(deps.storage, &env.block, market_id, Side::Sell)?;
+    let payment = match (bid, ask) {
+        (Some((_, bid)), Some((_, ask))) => {
+            let mid_price = (bid.price + ask.price) / Uint128::new(2);
+            let premium = to_signed(mid_price)?.checked_sub(to_signed(index_price)?)?;
+            let cap = to_signed(index_price * market.max_funding_rate)?;
+            premium.clamp(-cap, cap)
+        }
+        _ => Int128::zero(),
+    };
+    funding.cumulative_index = funding.cumulative_index.checked_add(payment)?;
+    funding.last_payment = payment;
+    funding.last_settled = now;
+    FUNDING.save(deps.storage, market_id, &funding)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "settle_funding")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("index_price", index_price)
+        .add_attribute("payment", payment.to_string())
+        .add_attribute("cumulative_index", funding.cumulative_index.to_string()))
+}
+
+pub fn try_liquidate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    market_id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_not_frozen(&market, market_id, env.block.time.seconds())?;
+    let owner = validate_account(deps.api, &owner)?;
+    let key = (&owner, market_id);
+    let mut position = POSITIONS
+        .may_load(deps.storage, key)?
+        .ok_or(ContractError::PositionNotFound { market_id })?;
+    let mark_price = fresh_mark_price(
+        &deps.querier,
+        &config.oracle,
+        &market,
+        env.block.time.seconds(),
+    )?;
+    let summary = liquidation_summary(deps.as_ref(), &config.oracle, &owner, market_id, &position)?;
+    let equity = summary.equity()?;
+    if equity >= summary.maintenance_margin {
+        return Err(ContractError::NotLiquidatable {
+            owner: owner.into_string(),
+        });
+    }
+    settle_funding(&mut position, funding_index(deps.storage, market_id)?)?;
+
+    // close half the position while the account, or the isolated position,
+    // keeps at least half of its maintenance margin, and all of it below that
+    let open_size = position.abs_size();
+    let half = open_size / Uint128::new(2);
+    let size = if equity + equity >= summary.maintenance_margin && half >= market.min_order_size {
+        half
+    } else {
+        open_size
+    };
+    let side = position.side();
+    let closed = signed_size(side, size)?;
+    let realized_pnl = pnl(closed, position.entry_price, mark_price)?;
+    let released = position.collateral.multiply_ratio(size, open_size);
+    let old_size = position.size;
+    position.size -= closed;
+    position.collateral -= released;
+    save_position(deps.storage, &owner, market_id, old_size, &position)?;
+
+    // the liquidator's bonus comes out of what the closed part leaves, then
+    // out of the account's free collateral under cross margin. The rest of
+    // the closed part goes to the treasury's insurance fund, which in turn
+    // covers any loss beyond it that the free collateral cannot.
+    let bonus = notional(size, mark_price).multiply_ratio(LIQUIDATION_BONUS_BPS, 10_000u128);
+    let proceeds = to_signed(released)?.checked_add(realized_pnl)?.i128();
+    let mut account = ACCOUNTS.may_load(deps.storage, &owner)?.unwrap_or_default();
+    let mut available = match account.mode {
+        MarginMode::Cross => account.collateral,
+        MarginMode::Isolated => Uint128::zero(),
+    };
+    let mut shortfall = Uint128::zero();
+    let mut uncovered = Uint128::zero();
+    let mut bonus_due = bonus;
+    if proceeds >= 0 {
+        let proceeds = Uint128::new(proceeds as u128);
+        let from_proceeds = bonus_due.min(proceeds);
+        bonus_due -= from_proceeds;
+        let insurance_fund = INSURANCE_FUND.may_load(deps.storage)?.unwrap_or_default();
+        INSURANCE_FUND.save(deps.storage, &(insurance_fund + proceeds - from_proceeds))?;
+    } else {
+        shortfall = Uint128::new(proceeds.unsigned_abs());
+        let from_account = shortfall.min(available);
+        available -= from_account;
+        account.collateral -= from_account;
+        uncovered = shortfall - from_account;
+    }
+    let from_account = bonus_due.min(available);
+    account.collateral -= from_account;
+    let bonus = bonus - bonus_due + from_account;
+    ACCOUNTS.save(deps.storage, &owner, &account)?;
+    // the insurance fund tops the bonus up to the minimum, so that accounts
+    // with nothing left to pay it are still worth liquidating
+    let min_bonus =
+        notional(size, mark_price).multiply_ratio(MIN_LIQUIDATION_BONUS_BPS, 10_000u128);
+    let insurance_fund = INSURANCE_FUND.may_load(deps.storage)?.unwrap_or_default();
+    let from_fund = min_bonus.saturating_sub(bonus).min(insurance_fund);
+    INSURANCE_FUND.save(deps.storage, &(insurance_fund - from_fund))?;
+    let bonus = bonus + from_fund;
+    let (bad_debt, events) =
+        cover_shortfall(deps.storage, market_id, side, size, mark_price, uncovered)?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "liquidate")
+        .add_attribute("owner", owner)
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("closed", size)
+        .add_attribute("mark_price", mark_price)
+        .add_attribute("realized_pnl", realized_pnl.to_string())
+        .add_attribute("bonus", bonus)
+        .add_attribute("shortfall", shortfall)
+        .add_attribute("bad_debt", bad_debt)
+        .add_events(events);
+    if !bonus.is_zero() {
+        res = res.add_message(send_collateral(&config.collateral, &info.sender, bonus)?);
+    }
+    Ok(res)
+}
+
+/// Covers a loss that a closed `size` of a position on `side` left beyond
+/// what its owner's margin could pay, first out of the insurance fund. What
+/// the fund cannot cover is taken from the profitable positions on the other
+/// side, which are reduced at the bankruptcy price. Returns the bad debt
+/// nothing covered and the `adl` events.
+fn cover_shortfall(
+    storage: &mut dyn Storage,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+    mark_price: Uint128,
+    shortfall: Uint128,
+) -> Result<(Uint128, Vec<Event>), ContractError> {
+    let mut insurance_fund = INSURANCE_FUND.may_load(storage)?.unwrap_or_default();
+    let from_fund = shortfall.min(insurance_fund);
+    insurance_fund -= from_fund;
+    let mut bad_debt = shortfall - from_fund;
+    let mut events = vec![];
+    if !bad_debt.is_zero() {
+        let (covered, adl_events) = auto_deleverage(
+            storage,
+            market_id,
+            side.opposite(),
+            size,
+            mark_price,
+            bad_debt,
+        )?;
+        insurance_fund += covered.saturating_sub(bad_debt);
+        bad_debt = bad_debt.saturating_sub(covered);
+        events = adl_events;
+    }
+    INSURANCE_FUND.save(storage, &insurance_fund)?;
+    Ok((bad_debt, events))
+}
+
+/// Closes up to `size` of the top ranked profitable positions on `side` at
+/// the bankruptcy price: the mark price moved against them far enough that
+/// the profit they give up covers `bad_debt`. What each closed part settles
+/// to is credited to its owner's margin account. Returns the amount covered
+/// and an `adl` event for each reduced position.
+fn auto_deleverage(
+    storage: &mut dyn Storage,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+    mark_price: Uint128,
+    bad_debt: Uint128,
+) -> Result<(Uint128, Vec<Event>), ContractError> {
+    let per_unit = bad_debt.checked_mul(Uint128::new(PRICE_PRECISION))?;
+    let per_unit = (per_unit + size - Uint128::one()) / size;
+    let bankruptcy_price = match side {
+        Side::Buy => mark_price.saturating_sub(per_unit),
+        Side::Sell => mark_price.checked_add(per_unit)?,
+    };
+
+    let funding_index = funding_index(storage, market_id)?;
+    let mut remaining = size;
+    let mut covered = Uint128::zero();
+    let mut events = vec![];
+    for candidate in adl_ranking(storage, market_id, side, mark_price)? {
+        if remaining.is_zero() {
+            break;
+        }
+        let mut position = candidate.position;
+        settle_funding(&mut position, funding_index)?;
+        let open_size = position.abs_size();
+        let reduced = remaining.min(open_size);
+        remaining -= reduced;
+        let closed = signed_size(side, reduced)?;
+        let released = position.collateral.multiply_ratio(reduced, open_size);
+        let realized_pnl = pnl(closed, position.entry_price, bankruptcy_price)?;
+        let payout = settle(released, realized_pnl)?;
+        covered += settle(released, pnl(closed, position.entry_price, mark_price)?)? - payout;
+
+        let old_size = position.size;
+        position.size -= closed;
+        position.collateral -= released;
+        save_position(storage, &candidate.owner, market_id, old_size, &position)?;
+        credit_account(storage, &candidate.owner, payout)?;
+        events.push(
+            Event::new("adl")
+                .add_attribute("owner", candidate.owner)
+                .add_attribute("market_id", market_id.to_string())
+                .add_attribute("size", reduced)
+                .add_attribute("price", bankruptcy_price)
+                .add_attribute("realized_pnl", realized_pnl.to_string()),
+        );
+    }
+    Ok((covered, events))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_place_trigger_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    side: Side,
+    kind: TriggerKind,
+    trigger_price: Uint128,
+    size: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_trading(&market, market_id, env.block.time.seconds())?;
+    let mut prices = vec![trigger_price];
+    if let TriggerKind::StopLimit { limit_price } = kind {
+        prices.push(limit_price);
+    }
+    for price in prices {
+        if price.is_zero() || !(price % market.tick_size).is_zero() {
+            return Err(ContractError::InvalidPrice {
+                price,
+                tick_size: market.tick_size,
+            });
+        }
+    }
+    validate_order_size(&market, size)?;
+    debit_account(deps.storage, &info.sender, config.trigger_fee)?;
+
+    let trigger_id = TRIGGER_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    TRIGGER_COUNT.save(deps.storage, &(trigger_id + 1))?;
+    let order = TriggerOrder {
+        market_id,
+        owner: info.sender,
+        side,
+        kind,
+        trigger_price,
+        size,
+        created_at: env.block.time.seconds(),
+        fee: config.trigger_fee,
+    };
+    trigger_orders().save(deps.storage, trigger_id, &order)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "place_trigger_order")
+        .add_attribute("trigger_id", trigger_id.to_string())
+        .add_attribute("trigger_price", trigger_price))
+}
+
+pub fn try_cancel_trigger_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    trigger_id: u64,
+) -> Result<Response, ContractError> {
+    let order = trigger_orders()
+        .may_load(deps.storage, trigger_id)?
+        .ok_or(ContractError::TriggerOrderNotFound { trigger_id })?;
+    if info.sender != order.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    trigger_orders().remove(deps.storage, trigger_id)?;
+    credit_account(deps.storage, &order.owner, order.fee)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "cancel_trigger_order")
+        .add_attribute("trigger_id", trigger_id.to_string()))
+}
+
+pub fn try_execute_triggers(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    ensure_trading(&market, market_id, env.block.time.seconds())?;
+    let mark_price = fresh_mark_price(
+        &deps.querier,
+        &config.oracle,
+        &market,
+        env.block.time.seconds(),
+    )?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    // only the span of trigger prices the mark has crossed is read: orders
+    // firing at or above their price from the lowest up, and those firing at
+    // or below it from the highest down
+    let mut triggered = vec![];
+    for (direction, order) in [(0, Order::Ascending), (1, Order::Descending)] {
+        triggered.extend(
+            trigger_orders()
+                .idx
+                .trigger
+                .sub_prefix((market_id, direction))
+                .range(deps.storage, None, None, order)
+                .take_while(|item| match item {
+                    Ok((_, order)) => order.triggered(mark_price),
+                    Err(_) => true,
+                })
+                .take(limit - triggered.len())
+                .collect::<StdResult<Vec<_>>>()?,
+        );
+    }
+
+    // each order runs in its own submessage, so a trigger that fails anywhere
+    // is rolled back and dropped in `reply` without reverting the crank
+    let count = triggered.len();
+    let messages = triggered
+        .into_iter()
+        .map(|(trigger_id, _)| {
+            let msg = WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                msg: to_binary(&ExecuteMsg::ExecuteTrigger {
+                    trigger_id,
+                    keeper: info.sender.to_string(),
+                    mark_price,
+                })?,
+                funds: vec![],
+            };
+            Ok(SubMsg::reply_on_error(msg, trigger_id))
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(Response::new()
+        .add_submessages(messages)
+        .add_attribute("method", "execute_triggers")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("mark_price", mark_price)
+        .add_attribute("triggered", count.to_string()))
+}
+
+pub fn try_execute_trigger(
+    deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    msg: InstantiateMsg,
+    trigger_id: u64,
+    keeper: String,
+    mark_price: Uint128,
 ) -> Result<Response, ContractError> {
-    let state = State {
-        count: msg.count,
-        owner: info.sender.clone(),
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let keeper = deps.api.addr_validate(&keeper)?;
+    let trigger = trigger_orders().load(deps.storage, trigger_id)?;
+    trigger_orders().remove(deps.storage, trigger_id)?;
+    let market = load_market(deps.storage, trigger.market_id)?;
+    let now = env.block.time.seconds();
+    let (price, rest) = match trigger.kind {
+        // the limit order rests like any other, so it is held to the band too
+        TriggerKind::StopLimit { limit_price } => {
+            validate_price_band(&deps.querier, &config.oracle, &market, limit_price, now)?;
+            (limit_price, true)
+        }
+        _ => (
+            slippage_price(&market, trigger.side, mark_price, TRIGGER_SLIPPAGE_BPS),
+            false,
+        ),
     };
-    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    STATE.save(deps.storage, &state)?;
+    let order = BookOrder {
+        market_id: trigger.market_id,
+        owner: trigger.owner,
+        side: trigger.side,
+        price,
+        size: trigger.size,
+        filled: Uint128::zero(),
+        created_at: now,
+        margin: Uint128::zero(),
+        expires: Expiration::Never {},
+        reduce_only: false,
+    };
+    let (order_id, _, trades, adl_events) =
+        place_order(deps.storage, &env.block, &market, order, rest)?;
+
+    let mut res = Response::new()
+        .add_events(trades.iter().map(|(id, trade)| fill_event(*id, trade)))
+        .add_events(adl_events)
+        .add_attribute("method", "execute_trigger")
+        .add_attribute("trigger_id", trigger_id.to_string())
+        .add_attribute("order_id", order_id.to_string())
+        .add_attribute("fee", trigger.fee);
+    if !trigger.fee.is_zero() {
+        res = res.add_message(send_collateral(&config.collateral, &keeper, trigger.fee)?);
+    }
+    Ok(res)
+}
+
+fn validate_fee_tiers(tiers: &[FeeTier]) -> Result<(), ContractError> {
+    let min_taker_fee_bps = tiers
+        .iter()
+        .map(|tier| tier.taker_fee_bps)
+        .min()
+        .unwrap_or_default();
+    let reason = if tiers.first().is_some_and(|tier| !tier.min_volume.is_zero()) {
+        "the first tier must start at zero volume"
+    } else if tiers
+        .windows(2)
+        .any(|pair| pair[0].min_volume >= pair[1].min_volume)
+    {
+        "tiers must be ordered by increasing min volume"
+    } else if tiers
+        .iter()
+        .any(|tier| tier.taker_fee_bps > MAX_FEE_BPS || tier.maker_fee_bps > MAX_FEE_BPS as i64)
+    {
+        "fees must be at most 100 bps"
+    } else if tiers
+        .iter()
+        .any(|tier| tier.maker_fee_bps < -(min_taker_fee_bps as i64))
+    {
+        "maker rebates must not exceed the lowest taker fee"
+    } else {
+        return Ok(());
+    };
+    Err(ContractError::InvalidFeeTiers {
+        reason: reason.to_string(),
+    })
+}
+
+pub fn try_update_fee_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    tiers: Vec<FeeTier>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    validate_fee_tiers(&tiers)?;
+    FEE_TIERS.save(deps.storage, &tiers)?;
 
     Ok(Response::new()
-        .add_attribute("method", "instantiate")
+        .add_attribute("method", "update_fee_tiers")
+        .add_attribute("tiers", tiers.len().to_string()))
+}
+
+pub fn try_set_max_batch_size(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_batch_size: u32,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    config.max_batch_size = max_batch_size;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "set_max_batch_size")
+        .add_attribute("max_batch_size", max_batch_size.to_string()))
+}
+
+pub fn try_set_referral_share(
+    deps: DepsMut,
+    info: MessageInfo,
+    referral_share_bps: u64,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if referral_share_bps > 10_000 {
+        return Err(ContractError::InvalidReferralShare {});
+    }
+    config.referral_share_bps = referral_share_bps;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "set_referral_share")
+        .add_attribute("referral_share_bps", referral_share_bps.to_string()))
+}
+
+pub fn try_register_referral_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+) -> Result<Response, ContractError> {
+    let valid = (3..=20).contains(&code.len())
+        && code
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        return Err(ContractError::InvalidReferralCode {});
+    }
+    if let Some(code) = ACCOUNT_REFERRAL_CODES.may_load(deps.storage, &info.sender)? {
+        return Err(ContractError::ReferralCodeAlreadySet { code });
+    }
+    if REFERRAL_CODES.has(deps.storage, &code) {
+        return Err(ContractError::ReferralCodeTaken { code });
+    }
+    REFERRAL_CODES.save(deps.storage, &code, &info.sender)?;
+    ACCOUNT_REFERRAL_CODES.save(deps.storage, &info.sender, &code)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "register_referral_code")
         .add_attribute("owner", info.sender)
-        .add_attribute("count", msg.count.to_string()))
+        .add_attribute("code", code))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn execute(
+pub fn try_claim_referral_rewards(
     deps: DepsMut,
-    _env: Env,
     info: MessageInfo,
-    msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
-    match msg {
-        ExecuteMsg::Increment {} => try_increment(deps),
-        ExecuteMsg::Reset { count } => try_reset(deps, info, count),
+    let config = CONFIG.load(deps.storage)?;
+    let mut referrals = REFERRALS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    let amount = std::mem::take(&mut referrals.unclaimed_rewards);
+    REFERRALS.save(deps.storage, &info.sender, &referrals)?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "claim_referral_rewards")
+        .add_attribute("owner", info.sender.as_str())
+        .add_attribute("amount", amount);
+    if !amount.is_zero() {
+        res = res.add_message(send_collateral(&config.collateral, &info.sender, amount)?);
+    }
+    Ok(res)
+}
+
+fn fund_insurance(storage: &mut dyn Storage, amount: Uint128) -> Result<Response, ContractError> {
+    let insurance_fund = INSURANCE_FUND.may_load(storage)?.unwrap_or_default() + amount;
+    INSURANCE_FUND.save(storage, &insurance_fund)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "fund_insurance")
+        .add_attribute("amount", amount)
+        .add_attribute("balance", insurance_fund))
+}
+
+pub fn try_fund_insurance(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let amount = collateral_sent(&info, &config.collateral)?;
+    fund_insurance(deps.storage, amount)
+}
+
+pub fn try_withdraw_insurance(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.treasury {
+        return Err(ContractError::Unauthorized {});
+    }
+    let balance = INSURANCE_FUND.may_load(deps.storage)?.unwrap_or_default();
+    let insurance_fund = balance
+        .checked_sub(amount)
+        .map_err(|_| ContractError::InsufficientInsuranceFund { balance })?;
+    INSURANCE_FUND.save(deps.storage, &insurance_fund)?;
+
+    Ok(Response::new()
+        .add_message(send_collateral(
+            &config.collateral,
+            &config.treasury,
+            amount,
+        )?)
+        .add_attribute("method", "withdraw_insurance")
+        .add_attribute("amount", amount)
+        .add_attribute("balance", insurance_fund))
+}
+
+pub fn try_sweep_fees(deps: DepsMut) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let amount = ACCRUED_FEES.may_load(deps.storage)?.unwrap_or_default();
+    ACCRUED_FEES.save(deps.storage, &Uint128::zero())?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "sweep_fees")
+        .add_attribute("treasury", config.treasury.as_str())
+        .add_attribute("amount", amount);
+    if !amount.is_zero() {
+        res = res.add_message(send_collateral(
+            &config.collateral,
+            &config.treasury,
+            amount,
+        )?);
+    }
+    Ok(res)
+}
+
+pub fn try_set_margin_mode(
+    deps: DepsMut,
+    info: MessageInfo,
+    mode: MarginMode,
+) -> Result<Response, ContractError> {
+    if POSITIONS
+        .prefix(&info.sender)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some()
+    {
+        return Err(ContractError::MarginModeLocked {});
+    }
+    let mut account = ACCOUNTS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    account.mode = mode;
+    ACCOUNTS.save(deps.storage, &info.sender, &account)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "set_margin_mode")
+        .add_attribute("owner", info.sender)
+        .add_attribute("mode", format!("{:?}", mode).to_lowercase()))
+}
+
+fn load_isolated_position(
+    storage: &dyn Storage,
+    owner: &Addr,
+    market_id: u64,
+) -> Result<Position, ContractError> {
+    if margin_mode(storage, owner)? != MarginMode::Isolated {
+        return Err(ContractError::IsolatedMarginOnly {});
+    }
+    let mut position = POSITIONS
+        .may_load(storage, (owner, market_id))?
+        .ok_or(ContractError::PositionNotFound { market_id })?;
+    settle_funding(&mut position, funding_index(storage, market_id)?)?;
+    Ok(position)
+}
+
+pub fn try_add_collateral(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_id: u64,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let mut position = load_isolated_position(deps.storage, &info.sender, market_id)?;
+    debit_account(deps.storage, &info.sender, amount)?;
+    position.collateral += amount;
+    POSITIONS.save(deps.storage, (&info.sender, market_id), &position)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "add_collateral")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("collateral", position.collateral))
+}
+
+pub fn try_remove_collateral(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_id: u64,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = load_market(deps.storage, market_id)?;
+    let now = env.block.time.seconds();
+    // a halted or expired market keeps its collateral for settlement
+    ensure_not_frozen(&market, market_id, now)?;
+    let mut position = load_isolated_position(deps.storage, &info.sender, market_id)?;
+    if amount > position.collateral {
+        return Err(ContractError::InsufficientMargin {
+            available: position.collateral,
+        });
+    }
+    position.collateral -= amount;
+
+    // what is left must still cover the initial margin at the mark price
+    let mark_price = fresh_mark_price(&deps.querier, &config.oracle, &market, now)?;
+    let equity = settle(position.collateral, unrealized_pnl(&position, mark_price)?)?;
+    if equity < initial_margin(position.abs_size(), mark_price) {
+        return Err(ContractError::LeverageTooHigh {
+            max_leverage: MAX_LEVERAGE,
+        });
     }
+    POSITIONS.save(deps.storage, (&info.sender, market_id), &position)?;
+    credit_account(deps.storage, &info.sender, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "remove_collateral")
+        .add_attribute("market_id", market_id.to_string())
+        .add_attribute("collateral", position.collateral))
+}
+
+pub fn try_create_sub_account(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let sub_account = SUB_ACCOUNT_COUNT
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default()
+        + 1;
+    SUB_ACCOUNT_COUNT.save(deps.storage, &info.sender, &sub_account)?;
+    let account = sub_account_addr(&info.sender, sub_account);
+    SUB_ACCOUNTS.save(deps.storage, (&info.sender, sub_account), &account)?;
+    ACCOUNT_OWNERS.save(deps.storage, &account, &info.sender)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "create_sub_account")
+        .add_attribute("owner", info.sender)
+        .add_attribute("sub_account", sub_account.to_string())
+        .add_attribute("account", account))
+}
+
+pub fn try_add_delegate(
+    deps: DepsMut,
+    info: MessageInfo,
+    sub_account: u32,
+    delegate: String,
+) -> Result<Response, ContractError> {
+    let account = load_sub_account(deps.storage, &info.sender, sub_account)?;
+    let delegate = deps.api.addr_validate(&delegate)?;
+    DELEGATES.save(deps.storage, (&account, &delegate), &Empty {})?;
+
+    Ok(Response::new()
+        .add_attribute("method", "add_delegate")
+        .add_attribute("account", account)
+        .add_attribute("delegate", delegate))
+}
+
+pub fn try_remove_delegate(
+    deps: DepsMut,
+    info: MessageInfo,
+    sub_account: u32,
+    delegate: String,
+) -> Result<Response, ContractError> {
+    let account = load_sub_account(deps.storage, &info.sender, sub_account)?;
+    let delegate = deps.api.addr_validate(&delegate)?;
+    DELEGATES.remove(deps.storage, (&account, &delegate));
+
+    Ok(Response::new()
+        .add_attribute("method", "remove_delegate")
+        .add_attribute("account", account)
+        .add_attribute("delegate", delegate))
 }
 
-pub fn try_increment(deps: DepsMut) -> Result<Response, ContractError> {
-    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-        state.count += 1;
-        Ok(state)
-    })?;
+/// Messages a delegate may send for an account.
+fn is_order_message(msg: &ExecuteMsg) -> bool {
+    matches!(
+        msg,
+        ExecuteMsg::PlaceLimitOrder { .. }
+            | ExecuteMsg::PlaceMarketOrder { .. }
+            | ExecuteMsg::CancelOrder { .. }
+            | ExecuteMsg::CancelAll { .. }
+            | ExecuteMsg::BatchOrders { .. }
+            | ExecuteMsg::ReplaceOrder { .. }
+            | ExecuteMsg::PlaceTriggerOrder { .. }
+            | ExecuteMsg::CancelTriggerOrder { .. }
+    )
+}
 
-    Ok(Response::new().add_attribute("method", "try_increment"))
+/// Messages an owner may send for one of its accounts.
+fn is_account_message(msg: &ExecuteMsg) -> bool {
+    is_order_message(msg)
+        || matches!(
+            msg,
+            ExecuteMsg::OpenPosition { .. }
+                | ExecuteMsg::ClosePosition { .. }
+                | ExecuteMsg::SettlePosition { .. }
+                | ExecuteMsg::Deposit { .. }
+                | ExecuteMsg::Withdraw { .. }
+                | ExecuteMsg::SetMarginMode { .. }
+                | ExecuteMsg::AddCollateral { .. }
+                | ExecuteMsg::RemoveCollateral { .. }
+        )
 }
 
-pub fn try_reset(deps: DepsMut, info: MessageInfo, count: i32) -> Result<Response, ContractError> {
-    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-        if info.sender != state.owner {
+pub fn try_with_account(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: Option<String>,
+    sub_account: u32,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let owner = match owner {
+        Some(owner) => deps.api.addr_validate(&owner)?,
+        None => info.sender.clone(),
+    };
+    let account = load_sub_account(deps.storage, &owner, sub_account)?;
+    if !is_account_message(&msg) {
+        return Err(ContractError::InvalidAccountMessage {});
+    }
+    if info.sender != owner {
+        if !DELEGATES.has(deps.storage, (&account, &info.sender)) {
             return Err(ContractError::Unauthorized {});
         }
-        state.count = count;
-        Ok(state)
-    })?;
-    Ok(Response::new().add_attribute("method", "reset"))
+        if !is_order_message(&msg) {
+            return Err(ContractError::DelegateNotAllowed {});
+        }
+    }
+
+    let sender = info.sender;
+    let info = MessageInfo {
+        sender: account,
+        funds: info.funds,
+    };
+    let res = execute(deps, env, info, msg)?;
+    Ok(res.add_attribute("sender", sender))
+}
+
+/// Handles the failed submessages of `ExecuteTriggers`: the failing order has
+/// been rolled back, so its trigger is cancelled and the fee refunded.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let trigger_id = msg.id;
+    let error = match msg.result {
+        SubMsgResult::Err(error) => error,
+        SubMsgResult::Ok(_) => return Ok(Response::new()),
+    };
+    let mut res = Response::new()
+        .add_attribute("method", "drop_trigger")
+        .add_attribute("trigger_id", trigger_id.to_string())
+        .add_attribute("error", error);
+    if let Some(trigger) = trigger_orders().may_load(deps.storage, trigger_id)? {
+        trigger_orders().remove(deps.storage, trigger_id)?;
+        credit_account(deps.storage, &trigger.owner, trigger.fee)?;
+        res = res.add_attribute("refund", trigger.fee);
+    }
+    Ok(res)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-        QueryMsg::GetCount {} => to_binary(&query_count(deps)?),
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Market { market_id } => to_binary(&query_market(deps, market_id)?),
+        QueryMsg::ListMarkets { start_after, limit } => {
+            to_binary(&query_list_markets(deps, start_after, limit)?)
+        }
+        QueryMsg::OrderBook { market_id, depth } => {
+            to_binary(&query_order_book(deps, env, market_id, depth)?)
+        }
+        QueryMsg::OrdersByOwner {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_orders_by_owner(deps, owner, start_after, limit)?),
+        QueryMsg::Position { owner, market_id } => {
+            to_binary(&query_position(deps, owner, market_id)?)
+        }
+        QueryMsg::MarginAccount { owner } => to_binary(&query_margin_account(deps, owner)?),
+        QueryMsg::LiquidatablePositions { start_after, limit } => {
+            to_binary(&query_liquidatable_positions(deps, start_after, limit)?)
+        }
+        QueryMsg::InsuranceFund {} => to_binary(&query_insurance_fund(deps)?),
+        QueryMsg::Funding { market_id } => to_binary(&query_funding(deps, market_id)?),
+        QueryMsg::MarketStats { market_id } => to_binary(&query_market_stats(deps, market_id)?),
+        QueryMsg::FeeTiers {} => to_binary(&query_fee_tiers(deps)?),
+        QueryMsg::AdlRanking {
+            market_id,
+            side,
+            limit,
+        } => to_binary(&query_adl_ranking(deps, market_id, side, limit)?),
+        QueryMsg::AccountFees { owner } => to_binary(&query_account_fees(deps, env, owner)?),
+        QueryMsg::TriggerOrdersByOwner {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_trigger_orders_by_owner(
+            deps,
+            owner,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::PositionsByOwner {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_positions_by_owner(deps, owner, start_after, limit)?),
+        QueryMsg::TradesByMarket {
+            market_id,
+            start_after,
+            start_time,
+            end_time,
+            limit,
+        } => to_binary(&query_trades_by_market(
+            deps,
+            market_id,
+            start_after,
+            start_time,
+            end_time,
+            limit,
+        )?),
+        QueryMsg::TradesByAccount {
+            owner,
+            start_after,
+            start_time,
+            end_time,
+            limit,
+        } => to_binary(&query_trades_by_account(
+            deps,
+            owner,
+            start_after,
+            start_time,
+            end_time,
+            limit,
+        )?),
+        QueryMsg::LastTradePrice { market_id } => {
+            to_binary(&query_last_trade_price(deps, market_id)?)
+        }
+        QueryMsg::ReferralStats { owner } => to_binary(&query_referral_stats(deps, owner)?),
+        QueryMsg::ReferralRewards { owner } => to_binary(&query_referral_rewards(deps, owner)?),
+        QueryMsg::SubAccounts {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_sub_accounts(deps, owner, start_after, limit)?),
+        QueryMsg::Delegates {
+            owner,
+            sub_account,
+            start_after,
+            limit,
+        } => to_binary(&query_delegates(
+            deps,
+            owner,
+            sub_account,
+            start_after,
+            limit,
+        )?),
+    }
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse {
+        owner: config.owner.to_string(),
+        oracle: config.oracle.to_string(),
+        collateral: config.collateral,
+        treasury: config.treasury.to_string(),
+        trigger_fee: config.trigger_fee,
+        max_batch_size: config.max_batch_size,
+        referral_share_bps: config.referral_share_bps,
+    })
+}
+
+fn query_market(deps: Deps, market_id: u64) -> StdResult<MarketResponse> {
+    let market = MARKETS.load(deps.storage, market_id)?;
+    Ok(MarketResponse { market_id, market })
+}
+
+fn query_list_markets(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<ListMarketsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let markets = MARKETS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(ListMarketsResponse { markets })
+}
+
+fn query_order_book(
+    deps: Deps,
+    env: Env,
+    market_id: u64,
+    depth: u32,
+) -> StdResult<OrderBookResponse> {
+    let depth = depth.min(MAX_DEPTH) as usize;
+    Ok(OrderBookResponse {
+        bids: price_levels(deps.storage, &env.block, market_id, Side::Buy, depth)?,
+        asks: price_levels(deps.storage, &env.block, market_id, Side::Sell, depth)?,
+    })
+}
+
+fn query_orders_by_owner(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<OrdersResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let orders = orders()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(OrdersResponse { orders })
+}
+
+fn position_response(
+    deps: Deps,
+    oracle: &Addr,
+    market_id: u64,
+    position: Position,
+) -> StdResult<PositionResponse> {
+    let market = MARKETS.load(deps.storage, market_id)?;
+    let mark_price = query_mark_price(&deps.querier, oracle, &market)?;
+    Ok(PositionResponse {
+        market_id,
+        unrealized_pnl: unrealized_pnl(&position, mark_price)?,
+        pending_funding: pending_funding(&position, funding_index(deps.storage, market_id)?)?,
+        leverage: leverage(&position),
+        mark_price,
+        position,
+    })
+}
+
+fn query_position(deps: Deps, owner: String, market_id: u64) -> StdResult<PositionResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let owner = validate_account(deps.api, &owner)?;
+    let position = POSITIONS.load(deps.storage, (&owner, market_id))?;
+    position_response(deps, &config.oracle, market_id, position)
+}
+
+fn query_positions_by_owner(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<PositionsResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let owner = validate_account(deps.api, &owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let positions = POSITIONS
+        .prefix(&owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (market_id, position) = item?;
+            position_response(deps, &config.oracle, market_id, position)
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PositionsResponse { positions })
+}
+
+fn query_margin_account(deps: Deps, owner: String) -> StdResult<MarginAccountResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let owner = validate_account(deps.api, &owner)?;
+    let mode = margin_mode(deps.storage, &owner)?;
+    let summary = margin_summary(deps, &config.oracle, &owner)?;
+    let margin_ratio = match mode {
+        MarginMode::Cross => summary.margin_ratio()?,
+        MarginMode::Isolated => {
+            let mut lowest: Option<Decimal> = None;
+            for item in POSITIONS
+                .prefix(&owner)
+                .range(deps.storage, None, None, Order::Ascending)
+            {
+                let (market_id, position) = item?;
+                let ratio =
+                    position_summary(deps, &config.oracle, market_id, &position)?.margin_ratio()?;
+                lowest = match (lowest, ratio) {
+                    (Some(lowest), Some(ratio)) => Some(lowest.min(ratio)),
+                    (lowest, ratio) => lowest.or(ratio),
+                };
+            }
+            lowest
+        }
+    };
+    Ok(MarginAccountResponse {
+        mode,
+        free_collateral: summary.collateral,
+        used_margin: summary.used_margin,
+        unrealized_pnl: summary.unrealized_pnl,
+        equity: summary.equity()?,
+        maintenance_margin: summary.maintenance_margin,
+        margin_ratio,
+    })
+}
+
+fn query_liquidatable_positions(
+    deps: Deps,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<LiquidatablePositionsResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = match start_after {
+        Some((owner, market_id)) => Some((validate_account(deps.api, &owner)?, market_id)),
+        None => None,
+    };
+    let start = start_after
+        .as_ref()
+        .map(|(owner, market_id)| Bound::exclusive((owner, *market_id)));
+
+    // positions are sorted by owner, so each cross margin account is
+    // summarized once, while isolated positions are checked on their own.
+    // At most `MAX_LIQUIDATION_SCAN` positions are read per query.
+    let mut summary: Option<(Addr, MarginSummary)> = None;
+    let mut positions = vec![];
+    let mut last = None;
+    let mut next_start_after = None;
+    let range = POSITIONS.range(deps.storage, start, None, Order::Ascending);
+    for (scanned, item) in range.enumerate() {
+        let ((owner, market_id), position) = item?;
+        if scanned == MAX_LIQUIDATION_SCAN || positions.len() == limit {
+            next_start_after = last;
+            break;
+        }
+        last = Some((owner.to_string(), market_id));
+        let isolated;
+        let account = match margin_mode(deps.storage, &owner)? {
+            MarginMode::Cross => {
+                if summary.as_ref().map(|(addr, _)| addr) != Some(&owner) {
+                    summary = Some((owner.clone(), margin_summary(deps, &config.oracle, &owner)?));
+                }
+                &summary.as_ref().unwrap().1
+            }
+            MarginMode::Isolated => {
+                isolated = position_summary(deps, &config.oracle, market_id, &position)?;
+                &isolated
+            }
+        };
+        if account.equity()? < account.maintenance_margin {
+            positions.push(LiquidatablePosition {
+                owner: owner.into_string(),
+                market_id,
+                size: position.size,
+                margin_ratio: account.margin_ratio()?.unwrap_or_default(),
+            });
+        }
     }
+    Ok(LiquidatablePositionsResponse {
+        positions,
+        next_start_after,
+    })
+}
+
+fn query_insurance_fund(deps: Deps) -> StdResult<InsuranceFundResponse> {
+    Ok(InsuranceFundResponse {
+        balance: INSURANCE_FUND.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_funding(deps: Deps, market_id: u64) -> StdResult<FundingResponse> {
+    MARKETS.load(deps.storage, market_id)?;
+    let funding = FUNDING
+        .may_load(deps.storage, market_id)?
+        .unwrap_or_default();
+    Ok(FundingResponse { market_id, funding })
+}
+
+fn query_trigger_orders_by_owner(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<TriggerOrdersResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let trigger_orders = trigger_orders()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(TriggerOrdersResponse { trigger_orders })
+}
+
+fn query_adl_ranking(
+    deps: Deps,
+    market_id: u64,
+    side: Side,
+    limit: Option<u32>,
+) -> StdResult<AdlRankingResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let market = MARKETS.load(deps.storage, market_id)?;
+    let mark_price = query_mark_price(&deps.querier, &config.oracle, &market)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let positions = adl_ranking(deps.storage, market_id, side, mark_price)?
+        .into_iter()
+        .take(limit)
+        .map(|candidate| AdlRank {
+            owner: candidate.owner.into_string(),
+            size: candidate.position.size,
+            unrealized_pnl: candidate.unrealized_pnl,
+            score: candidate.score,
+        })
+        .collect();
+    Ok(AdlRankingResponse {
+        market_id,
+        mark_price,
+        positions,
+    })
+}
+
+fn query_fee_tiers(deps: Deps) -> StdResult<FeeTiersResponse> {
+    Ok(FeeTiersResponse {
+        tiers: FEE_TIERS.may_load(deps.storage)?.unwrap_or_default(),
+        accrued_fees: ACCRUED_FEES.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_account_fees(deps: Deps, env: Env, owner: String) -> StdResult<AccountFeesResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let tiers = FEE_TIERS.may_load(deps.storage)?.unwrap_or_default();
+    let volume = rolling_volume(deps.storage, &owner, env.block.time.seconds())?;
+    let (tier, fees) = fee_tier(&tiers, volume);
+    Ok(AccountFeesResponse {
+        volume,
+        tier: tier as u32,
+        maker_fee_bps: fees.maker_fee_bps,
+        taker_fee_bps: fees.taker_fee_bps,
+    })
+}
+
+fn query_trades_by_market(
+    deps: Deps,
+    market_id: u64,
+    start_after: Option<u64>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<TradesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let trades = trades_page(
+        deps.storage,
+        MARKET_TRADES.sub_prefix(market_id),
+        start_after,
+        start_time,
+        end_time,
+        limit,
+    )?;
+    Ok(TradesResponse { trades })
+}
+
+fn query_trades_by_account(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u64>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<TradesResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let trades = trades_page(
+        deps.storage,
+        ACCOUNT_TRADES.sub_prefix(&owner),
+        start_after,
+        start_time,
+        end_time,
+        limit,
+    )?;
+    Ok(TradesResponse { trades })
+}
+
+fn query_last_trade_price(deps: Deps, market_id: u64) -> StdResult<LastTradePriceResponse> {
+    let last = trades_page(
+        deps.storage,
+        MARKET_TRADES.sub_prefix(market_id),
+        None,
+        None,
+        None,
+        1,
+    )?;
+    let trade = last.into_iter().next().map(|(_, trade)| trade);
+    Ok(LastTradePriceResponse {
+        market_id,
+        price: trade.as_ref().map(|trade| trade.price),
+        timestamp: trade.map(|trade| trade.timestamp),
+    })
+}
+
+fn query_referral_stats(deps: Deps, owner: String) -> StdResult<ReferralStatsResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let referrals = REFERRALS
+        .may_load(deps.storage, &owner)?
+        .unwrap_or_default();
+    Ok(ReferralStatsResponse {
+        code: ACCOUNT_REFERRAL_CODES.may_load(deps.storage, &owner)?,
+        referrer: REFERRERS
+            .may_load(deps.storage, &owner)?
+            .map(|referrer| referrer.to_string()),
+        referred_accounts: referrals.referred_accounts,
+        referred_volume: referrals.referred_volume,
+        total_rewards: referrals.total_rewards,
+    })
+}
+
+fn query_referral_rewards(deps: Deps, owner: String) -> StdResult<ReferralRewardsResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let referrals = REFERRALS
+        .may_load(deps.storage, &owner)?
+        .unwrap_or_default();
+    Ok(ReferralRewardsResponse {
+        unclaimed: referrals.unclaimed_rewards,
+        claimed: referrals.total_rewards - referrals.unclaimed_rewards,
+    })
+}
+
+fn query_sub_accounts(
+    deps: Deps,
+    owner: String,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+) -> StdResult<SubAccountsResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let sub_accounts = SUB_ACCOUNTS
+        .prefix(&owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            item.map(|(sub_account, account)| SubAccount {
+                sub_account,
+                account: account.to_string(),
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(SubAccountsResponse { sub_accounts })
 }
 
-fn query_count(deps: Deps) -> StdResult<GetCountResponse> {
-    let state = STATE.load(deps.storage)?;
-    Ok(GetCountResponse { count: state.count })
+fn query_delegates(
+    deps: Deps,
+    owner: String,
+    sub_account: u32,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<DelegatesResponse> {
+    let owner = validate_account(deps.api, &owner)?;
+    let account = sub_account_addr(&owner, sub_account);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after
+        .map(|delegate| deps.api.addr_validate(&delegate))
+        .transpose()?;
+    let start = start_after.as_ref().map(Bound::exclusive);
+    let delegates = DELEGATES
+        .prefix(&account)
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|delegate| delegate.to_string()))
+        .collect::<StdResult<_>>()?;
+    Ok(DelegatesResponse {
+        account: account.to_string(),
+        delegates,
+    })
+}
+
+fn query_market_stats(deps: Deps, market_id: u64) -> StdResult<MarketStatsResponse> {
+    let market = MARKETS.load(deps.storage, market_id)?;
+    let open_interest = OPEN_INTEREST
+        .may_load(deps.storage, market_id)?
+        .unwrap_or_default();
+    Ok(MarketStatsResponse {
+        market_id,
+        long_open_interest: open_interest.long,
+        short_open_interest: open_interest.short,
+        max_open_interest: market.max_open_interest,
+        remaining_long_capacity: market.max_open_interest.saturating_sub(open_interest.long),
+        remaining_short_capacity: market.max_open_interest.saturating_sub(open_interest.short),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coins, from_binary};
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        coins, ContractResult, Decimal, Int128, OwnedDeps, SystemResult, WasmQuery,
+    };
+    use oracle::msg::GetCurrentPriceResponse;
+    use oracle::state::Price;
+
+    fn create_market_msg() -> ExecuteMsg {
+        ExecuteMsg::CreateMarket {
+            base_asset: "btc".to_string(),
+            quote_asset: "usd".to_string(),
+            max_bet_size: Uint128::new(1_000_000),
+            tick_size: Uint128::new(100),
+            min_order_size: Uint128::new(10),
+            max_order_size: Uint128::new(1_000_000),
+            max_open_interest: Uint128::new(10_000_000),
+            max_funding_rate: Decimal::percent(1),
+            price_band_bps: None,
+            max_price_age: None,
+            expiry: None,
+        }
+    }
+
+    #[test]
+    fn create_update_close_market() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        // only the owner can list markets
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            create_market_msg(),
+        );
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+
+        let msg = ExecuteMsg::UpdateMarket {
+            market_id: 0,
+            max_bet_size: None,
+            tick_size: Some(Uint128::zero()),
+            min_order_size: None,
+            max_order_size: None,
+            max_open_interest: None,
+            max_funding_rate: None,
+            price_band_bps: None,
+            max_price_age: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::InvalidMarketParams { .. })
+        ));
+
+        let msg = ExecuteMsg::UpdateMarket {
+            market_id: 0,
+            max_bet_size: Some(Uint128::new(5_000_000)),
+            tick_size: None,
+            min_order_size: None,
+            max_order_size: None,
+            max_open_interest: None,
+            max_funding_rate: None,
+            price_band_bps: None,
+            max_price_age: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::CloseMarket { market_id: 0 };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Market { market_id: 0 }).unwrap();
+        let value: MarketResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(5_000_000), value.market.max_bet_size);
+        assert_eq!(MarketStatus::Closed, value.market.status);
+
+        let msg = ExecuteMsg::CloseMarket { market_id: 0 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::MarketClosed { market_id: 0 })
+        ));
+    }
+
+    fn place(deps: DepsMut, sender: &str, side: Side, price: u128, size: u128) -> Response {
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side,
+            price: Uint128::new(price),
+            size: Uint128::new(size),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        execute(deps, mock_env(), mock_info(sender, &[]), msg).unwrap()
+    }
+
+    #[test]
+    fn limit_orders_match_by_price_time() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+
+        // off-tick prices and dust orders are rejected
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(150),
+            size: Uint128::new(10),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(res, Err(ContractError::InvalidPrice { .. })));
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(100),
+            size: Uint128::new(5),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(res, Err(ContractError::OrderTooSmall { .. })));
+
+        place(deps.as_mut(), "alice", Side::Sell, 300, 10);
+        place(deps.as_mut(), "bob", Side::Sell, 200, 10);
+        place(deps.as_mut(), "carol", Side::Sell, 200, 10);
+        place(deps.as_mut(), "dave", Side::Buy, 100, 20);
+
+        // crosses bob, then carol at 200, and stops before alice at 300
+        let res = place(deps.as_mut(), "erin", Side::Buy, 200, 15);
+        assert_eq!(res.attributes[2].value, "2");
+        assert_eq!(res.attributes[3].value, "15");
+        assert_eq!(res.attributes[4].value, "0");
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::OrderBook {
+                market_id: 0,
+                depth: 10,
+            },
+        )
+        .unwrap();
+        let value: OrderBookResponse = from_binary(&res).unwrap();
+        assert_eq!(value.bids.len(), 1);
+        assert_eq!(value.bids[0].size, Uint128::new(20));
+        assert_eq!(value.asks.len(), 2);
+        assert_eq!(value.asks[0].price, Uint128::new(200));
+        assert_eq!(value.asks[0].size, Uint128::new(5));
+        assert_eq!(value.asks[1].price, Uint128::new(300));
+
+        // a crossing remainder rests on the book at its limit price
+        let res = place(deps.as_mut(), "erin", Side::Sell, 100, 30);
+        assert_eq!(res.attributes[3].value, "20");
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::OrderBook {
+                market_id: 0,
+                depth: 1,
+            },
+        )
+        .unwrap();
+        let value: OrderBookResponse = from_binary(&res).unwrap();
+        assert!(value.bids.is_empty());
+        assert_eq!(value.asks[0].price, Uint128::new(100));
+        assert_eq!(value.asks[0].size, Uint128::new(10));
+    }
+
+    #[test]
+    fn order_position_and_open_interest_limits() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let msg = ExecuteMsg::CreateMarket {
+            base_asset: "btc".to_string(),
+            quote_asset: "usd".to_string(),
+            max_bet_size: Uint128::new(30),
+            tick_size: Uint128::new(100),
+            min_order_size: Uint128::new(10),
+            max_order_size: Uint128::new(20),
+            max_open_interest: Uint128::new(40),
+            max_funding_rate: Decimal::percent(1),
+            price_band_bps: None,
+            max_price_age: None,
+            expiry: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(100),
+            size: Uint128::new(25),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg);
+        assert!(matches!(res, Err(ContractError::OrderTooLarge { .. })));
+
+        // bob goes long 20, so another 20 would take him past the max bet size
+        place(deps.as_mut(), "alice", Side::Sell, 100, 20);
+        place(deps.as_mut(), "bob", Side::Buy, 100, 20);
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(100),
+            size: Uint128::new(20),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg);
+        assert!(matches!(res, Err(ContractError::PositionTooLarge { .. })));
+
+        // fills up to the open interest cap go through, and alice buying back
+        // part of her short leaves open interest unchanged
+        place(deps.as_mut(), "carol", Side::Sell, 100, 20);
+        place(deps.as_mut(), "dave", Side::Buy, 100, 20);
+        place(deps.as_mut(), "erin", Side::Sell, 100, 10);
+        place(deps.as_mut(), "alice", Side::Buy, 100, 10);
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::MarketStats { market_id: 0 },
+        )
+        .unwrap();
+        let value: MarketStatsResponse = from_binary(&res).unwrap();
+        assert_eq!(value.long_open_interest, Uint128::new(40));
+        assert_eq!(value.short_open_interest, Uint128::new(40));
+        assert_eq!(value.remaining_long_capacity, Uint128::zero());
+        assert_eq!(value.remaining_short_capacity, Uint128::zero());
+
+        // a fill past the cap fails, reverting the whole order
+        place(deps.as_mut(), "erin", Side::Sell, 100, 10);
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(100),
+            size: Uint128::new(10),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("frank", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::OpenInterestCapExceeded { .. })
+        ));
+    }
+
+    fn set_mark_price(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128) {
+        deps.querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { .. } => {
+                let res = GetCurrentPriceResponse {
+                    price: Price {
+                        amount: Uint128::new(amount),
+                        timestamp: 0,
+                    },
+                };
+                SystemResult::Ok(ContractResult::Ok(to_binary(&res).unwrap()))
+            }
+            _ => panic!("unexpected query"),
+        });
+    }
 
     #[test]
-    fn proper_initialization() {
+    fn market_orders_walk_the_book() {
         let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+        set_mark_price(&mut deps, 200);
+
+        place(deps.as_mut(), "alice", Side::Sell, 200, 10);
+        place(deps.as_mut(), "bob", Side::Sell, 300, 10);
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(1000, "earth"));
+        // post-only orders rest below the best ask, but not at it
+        let post_only = |price: u128| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(price),
+            size: Uint128::new(10),
+            post_only: true,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("carol", &[]),
+            post_only(200),
+        );
+        assert!(matches!(res, Err(ContractError::PostOnlyWouldCross { .. })));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("carol", &[]),
+            post_only(100),
+        )
+        .unwrap();
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let market_order =
+            |max_slippage_bps: u64, time_in_force: TimeInForce| ExecuteMsg::PlaceMarketOrder {
+                market_id: 0,
+                side: Side::Buy,
+                size: Uint128::new(30),
+                max_slippage_bps,
+                time_in_force,
+                reduce_only: false,
+            };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dave", &[]),
+            market_order(5_000, TimeInForce::Fok),
+        );
+        assert!(matches!(
+            res,
+            Err(ContractError::InsufficientLiquidity { available }) if available == Uint128::new(20)
+        ));
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(17, value.count);
+        // the 20 available fill at an average of 250, 25% above the mark
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dave", &[]),
+            market_order(1_000, TimeInForce::Ioc),
+        );
+        assert!(matches!(res, Err(ContractError::SlippageTooHigh { .. })));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("dave", &[]),
+            market_order(2_500, TimeInForce::Ioc),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[3].value, "20");
+        assert_eq!(res.attributes[4].value, "250");
+        assert_eq!(res.attributes[5].value, "10");
+
+        // the unfilled rest does not stay on the book
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::OrderBook {
+                market_id: 0,
+                depth: 10,
+            },
+        )
+        .unwrap();
+        let value: OrderBookResponse = from_binary(&res).unwrap();
+        assert!(value.asks.is_empty());
+        assert_eq!(value.bids.len(), 1);
+        assert_eq!(value.bids[0].price, Uint128::new(100));
     }
 
     #[test]
-    fn increment() {
+    fn batch_and_replace_orders() {
         let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::SetMaxBatchSize { max_batch_size: 3 };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        place(deps.as_mut(), "alice", Side::Sell, 200, 10);
+        place(deps.as_mut(), "alice", Side::Sell, 300, 10);
+        let ask = |price: u128| LimitOrder {
+            market_id: 0,
+            side: Side::Sell,
+            price: Uint128::new(price),
+            size: Uint128::new(10),
+            post_only: true,
+            expires: None,
+            reduce_only: false,
+        };
+        let msg = ExecuteMsg::BatchOrders {
+            cancels: vec![0, 1],
+            places: vec![ask(400), ask(500)],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::BatchTooLarge { max_batch_size: 3 })
+        ));
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(2, "token"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // the whole side is requoted in one go
+        let msg = ExecuteMsg::BatchOrders {
+            cancels: vec![0],
+            places: vec![ask(400), ask(500)],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let value: BatchOrdersResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(value.cancelled.len(), 1);
+        assert_eq!(value.cancelled[0].order_id, 0);
+        let ids: Vec<u64> = value.placed.iter().map(|order| order.order_id).collect();
+        assert_eq!(ids, vec![2, 3]);
 
-        // beneficiary can release it
-        let info = mock_info("anyone", &coins(2, "token"));
-        let msg = ExecuteMsg::Increment {};
-        let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // replacing keeps the market and side but takes a new place in line
+        let msg = ExecuteMsg::ReplaceOrder {
+            order_id: 1,
+            price: Uint128::new(400),
+            size: Uint128::new(20),
+            post_only: false,
+            expires: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let value: PlacedOrder = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(value.order_id, 4);
+        assert_eq!(value.remaining, Uint128::new(20));
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::OrderBook {
+                market_id: 0,
+                depth: 10,
+            },
+        )
+        .unwrap();
+        let value: OrderBookResponse = from_binary(&res).unwrap();
+        assert_eq!(value.asks.len(), 2);
+        assert_eq!(value.asks[0].price, Uint128::new(400));
+        assert_eq!(value.asks[0].size, Uint128::new(30));
 
-        // should increase counter by 1
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(18, value.count);
+        // a batch fails as a whole on an order that is not the sender's
+        let msg = ExecuteMsg::BatchOrders {
+            cancels: vec![2],
+            places: vec![],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg);
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
     }
 
     #[test]
-    fn reset() {
+    fn expired_orders_stop_matching_and_are_pruned() {
         let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+
+        let env = mock_env();
+        let ask = |price: u128, expires: Expiration| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Sell,
+            price: Uint128::new(price),
+            size: Uint128::new(10),
+            post_only: false,
+            expires: Some(expires),
+            reduce_only: false,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ask(200, Expiration::AtHeight(env.block.height)),
+        );
+        assert!(matches!(res, Err(ContractError::InvalidExpiry {})));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ask(200, Expiration::AtHeight(env.block.height + 10)),
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ask(300, Expiration::AtTime(env.block.time.plus_seconds(3600))),
+        )
+        .unwrap();
+
+        // past alice's height, her better price no longer matches
+        let mut env = mock_env();
+        env.block.height += 10;
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(300),
+            size: Uint128::new(10),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("carol", &[]), msg).unwrap();
+        assert_eq!(res.attributes[2].value, "1");
+        let trades: TradesResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::TradesByMarket {
+                    market_id: 0,
+                    start_after: None,
+                    start_time: None,
+                    end_time: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(trades.trades[0].1.maker, "bob");
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::OrderBook {
+                market_id: 0,
+                depth: 10,
+            },
+        )
+        .unwrap();
+        let value: OrderBookResponse = from_binary(&res).unwrap();
+        assert!(value.asks.is_empty());
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(2, "token"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // anyone can clear the expired order off the book
+        let msg = ExecuteMsg::PruneExpired {
+            market_id: 0,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("dave", &[]), msg).unwrap();
+        assert_eq!(res.attributes[2].value, "1");
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::OrdersByOwner {
+                owner: "alice".to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: OrdersResponse = from_binary(&res).unwrap();
+        assert!(value.orders.is_empty());
+    }
+
+    #[test]
+    fn open_and_close_position() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Native {
+                denom: "uusd".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+        set_mark_price(&mut deps, 2_000_000);
 
-        // beneficiary can release it
-        let unauth_info = mock_info("anyone", &coins(2, "token"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let res = execute(deps.as_mut(), mock_env(), unauth_info, msg);
-        match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Must return unauthorized error"),
+        for (addr, amount) in [("alice", 1000), ("bob", 125)] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(addr, &coins(amount, "uusd")),
+                ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+            )
+            .unwrap();
         }
 
-        // only the original creator can reset the counter
-        let auth_info = mock_info("creator", &coins(2, "token"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg).unwrap();
+        let open = |side, collateral: u128| ExecuteMsg::OpenPosition {
+            market_id: 0,
+            side,
+            size: Uint128::new(1000),
+            collateral: Uint128::new(collateral),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            open(Side::Buy, 99),
+        );
+        assert!(matches!(
+            res,
+            Err(ContractError::LeverageTooHigh { max_leverage: 20 })
+        ));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            open(Side::Buy, 1001),
+        );
+        assert!(matches!(res, Err(ContractError::InsufficientMargin { .. })));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            open(Side::Buy, 200),
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            open(Side::Sell, 200),
+        );
+        assert!(matches!(
+            res,
+            Err(ContractError::OppositePosition { market_id: 0 })
+        ));
+
+        set_mark_price(&mut deps, 2_500_000);
+        let msg = QueryMsg::Position {
+            owner: "alice".to_string(),
+            market_id: 0,
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: PositionResponse = from_binary(&res).unwrap();
+        assert_eq!(Int128::new(500), value.unrealized_pnl);
+        assert_eq!(Decimal::percent(1000), value.leverage);
+
+        // closing half credits half the pnl and half the collateral to the
+        // margin account
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: Some(Uint128::new(500)),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        let account = ACCOUNTS
+            .load(&deps.storage, &Addr::unchecked("alice"))
+            .unwrap();
+        assert_eq!(account.collateral, Uint128::new(1150));
+
+        // a short loses as the price rises, and its account never goes below
+        // zero
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bob", &[]),
+            open(Side::Sell, 125),
+        )
+        .unwrap();
+        set_mark_price(&mut deps, 3_000_000);
+        let msg = QueryMsg::PositionsByOwner {
+            owner: "bob".to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let value: PositionsResponse = from_binary(&res).unwrap();
+        assert_eq!(Int128::new(-500), value.positions[0].unrealized_pnl);
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap();
+        let account = ACCOUNTS
+            .load(&deps.storage, &Addr::unchecked("bob"))
+            .unwrap();
+        assert_eq!(account.collateral, Uint128::zero());
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Position {
+                owner: "bob".to_string(),
+                market_id: 0,
+            },
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn cw20_margin_deposit_and_withdraw() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Cw20 {
+                address: "token".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let deposit = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(300),
+            msg: to_binary(&ReceiveMsg::Deposit {
+                sub_account: None,
+                referral_code: None,
+            })
+            .unwrap(),
+        });
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("fake", &[]),
+            deposit.clone(),
+        );
+        assert!(matches!(res, Err(ContractError::InvalidCollateral {})));
+        execute(deps.as_mut(), mock_env(), mock_info("token", &[]), deposit).unwrap();
+
+        // native coins are not accepted when the collateral is a CW20
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &coins(300, "uusd")),
+            ExecuteMsg::Deposit {
+                referral_code: None,
+            },
+        );
+        assert!(matches!(res, Err(ContractError::InvalidCollateral {})));
+
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(301),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(res, Err(ContractError::InsufficientMargin { .. })));
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(300),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "alice".to_string(),
+                    amount: Uint128::new(300),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn cw20_collateral_opens_and_closes_positions() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: "owner".to_string(),
+            oracle: "oracle".to_string(),
+            collateral: Collateral::Cw20 {
+                address: "token".to_string(),
+            },
+            treasury: "treasury".to_string(),
+            trigger_fee: Uint128::zero(),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            create_market_msg(),
+        )
+        .unwrap();
+        set_mark_price(&mut deps, 2_000_000);
+
+        let deposit = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(300),
+            msg: to_binary(&ReceiveMsg::Deposit {
+                sub_account: None,
+                referral_code: None,
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("token", &[]), deposit).unwrap();
+
+        // the position is collateralized out of the margin account
+        let msg = ExecuteMsg::OpenPosition {
+            market_id: 0,
+            side: Side::Buy,
+            size: Uint128::new(1000),
+            collateral: Uint128::new(100),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::MarginAccount {
+                owner: "alice".to_string(),
+            },
+        )
+        .unwrap();
+        let value: MarginAccountResponse = from_binary(&res).unwrap();
+        assert_eq!(value.free_collateral, Uint128::new(200));
+        assert_eq!(value.used_margin, Uint128::new(100));
 
-        // should now be 5
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(5, value.count);
+        // and closing returns it there, to be withdrawn as the token
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(300),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "alice".to_string(),
+                    amount: Uint128::new(300),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
     }
 }
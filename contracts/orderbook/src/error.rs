@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,11 +6,154 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
     #[error("Custom Error val: {val:?}")]
     CustomError { val: String },
+
+    #[error("Invalid market parameters: {reason}")]
+    InvalidMarketParams { reason: String },
+
+    #[error("Invalid fee tiers: {reason}")]
+    InvalidFeeTiers { reason: String },
+
+    #[error("Referral share must be at most 10000 bps")]
+    InvalidReferralShare {},
+
+    #[error("Referral codes are 3 to 20 lowercase letters, digits, dashes or underscores")]
+    InvalidReferralCode {},
+
+    #[error("Referral code {code} is already taken")]
+    ReferralCodeTaken { code: String },
+
+    #[error("Account already has the referral code {code}")]
+    ReferralCodeAlreadySet { code: String },
+
+    #[error("Accounts cannot use their own referral code")]
+    SelfReferral {},
+
+    #[error("Referral code {code} not found")]
+    ReferralCodeNotFound { code: String },
+
+    #[error("No position in market {market_id} for a reduce-only order to reduce")]
+    NothingToReduce { market_id: u64 },
+
+    #[error("Market {market_id} not found")]
+    MarketNotFound { market_id: u64 },
+
+    #[error("Market {market_id} is halted")]
+    MarketHalted { market_id: u64 },
+
+    #[error("Market {market_id} is not halted")]
+    MarketNotHalted { market_id: u64 },
+
+    #[error("Market {market_id} has expired")]
+    MarketExpired { market_id: u64 },
+
+    #[error("Market {market_id} does not settle until {expiry}")]
+    MarketNotExpired { market_id: u64, expiry: u64 },
+
+    #[error("Market {market_id} is closed")]
+    MarketClosed { market_id: u64 },
+
+    #[error("Price {price} must be a positive multiple of the tick size {tick_size}")]
+    InvalidPrice { price: Uint128, tick_size: Uint128 },
+
+    #[error("Order size must be at least {min_order_size}")]
+    OrderTooSmall { min_order_size: Uint128 },
+
+    #[error("Order size must be at most {max_order_size}")]
+    OrderTooLarge { max_order_size: Uint128 },
+
+    #[error("Position size would exceed the limit of {max_bet_size}")]
+    PositionTooLarge { max_bet_size: Uint128 },
+
+    #[error("Open interest would exceed the cap of {max_open_interest}")]
+    OpenInterestCapExceeded { max_open_interest: Uint128 },
+
+    #[error("Price {price} is outside the band of {min_price} to {max_price} around the mark price {mark_price}")]
+    PriceOutsideBand {
+        price: Uint128,
+        mark_price: Uint128,
+        min_price: Uint128,
+        max_price: Uint128,
+    },
+
+    #[error("Oracle price set at {updated_at} is older than the {max_price_age} seconds allowed")]
+    StalePrice { updated_at: u64, max_price_age: u64 },
+
+    #[error("Post-only order would match the resting order at {best_price}")]
+    PostOnlyWouldCross { best_price: Uint128 },
+
+    #[error("Only {available} can be filled from the book")]
+    InsufficientLiquidity { available: Uint128 },
+
+    #[error("Average fill price {average_price} is too far from the mark price {mark_price}")]
+    SlippageTooHigh {
+        average_price: Uint128,
+        mark_price: Uint128,
+    },
+
+    #[error("Order expiry must be in the future")]
+    InvalidExpiry {},
+
+    #[error("A batch can hold at most {max_batch_size} orders")]
+    BatchTooLarge { max_batch_size: u32 },
+
+    #[error("Order {order_id} not found")]
+    OrderNotFound { order_id: u64 },
+
+    #[error("Trigger order {trigger_id} not found")]
+    TriggerOrderNotFound { trigger_id: u64 },
+
+    #[error("Must send a non-zero amount of the collateral asset and nothing else")]
+    InvalidCollateral {},
+
+    #[error("Insufficient free collateral, {available} available")]
+    InsufficientMargin { available: Uint128 },
+
+    #[error("Insurance fund only holds {balance}")]
+    InsufficientInsuranceFund { balance: Uint128 },
+
+    #[error("Account would fall below its maintenance margin of {maintenance_margin}")]
+    BelowMaintenanceMargin { maintenance_margin: Uint128 },
+
+    #[error("Margin mode can only change without open positions")]
+    MarginModeLocked {},
+
+    #[error("Only isolated positions hold their own collateral")]
+    IsolatedMarginOnly {},
+
+    #[error("Sub-account {sub_account} not found")]
+    SubAccountNotFound { sub_account: u32 },
+
+    #[error("Message cannot be sent on behalf of an account")]
+    InvalidAccountMessage {},
+
+    #[error("Delegates can only place and cancel orders")]
+    DelegateNotAllowed {},
+
+    #[error("Account {owner} is above its maintenance margin")]
+    NotLiquidatable { owner: String },
+
+    #[error("Funding can next be settled at {next_funding}")]
+    FundingNotDue { next_funding: u64 },
+
+    #[error("Leverage cannot exceed {max_leverage}x")]
+    LeverageTooHigh { max_leverage: u64 },
+
+    #[error("Already holding an opposite position in market {market_id}")]
+    OppositePosition { market_id: u64 },
+
+    #[error("No position in market {market_id}")]
+    PositionNotFound { market_id: u64 },
+
+    #[error("Cannot close more than the position size {size}")]
+    CloseTooLarge { size: Uint128 },
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -0,0 +1,96 @@
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Uint128};
+use cw_storage_plus::Bound;
+
+use crate::state::{FeeTier, REFERRALS, REFERRERS, VOLUMES};
+
+/// Largest maker or taker fee a tier may charge, in basis points.
+pub const MAX_FEE_BPS: u64 = 100;
+/// Days of traded volume that count towards an account's fee tier.
+pub const VOLUME_WINDOW_DAYS: u64 = 30;
+const DAY: u64 = 24 * 60 * 60;
+
+/// Oldest day, counted from the unix epoch, inside the volume window at `now`.
+fn window_start(now: u64) -> u64 {
+    (now / DAY + 1).saturating_sub(VOLUME_WINDOW_DAYS)
+}
+
+/// Adds `notional` to the owner's volume for the current day and drops the
+/// days that have left the window.
+pub fn record_volume(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    now: u64,
+    notional: Uint128,
+) -> StdResult<()> {
+    let expired = VOLUMES
+        .prefix(owner)
+        .keys(
+            storage,
+            None,
+            Some(Bound::exclusive(window_start(now))),
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<_>>>()?;
+    for day in expired {
+        VOLUMES.remove(storage, (owner, day));
+    }
+    VOLUMES.update(storage, (owner, now / DAY), |volume| -> StdResult<_> {
+        Ok(volume.unwrap_or_default() + notional)
+    })?;
+    Ok(())
+}
+
+/// Notional the owner traded over the last `VOLUME_WINDOW_DAYS` days,
+/// including today.
+pub fn rolling_volume(storage: &dyn Storage, owner: &Addr, now: u64) -> StdResult<Uint128> {
+    VOLUMES
+        .prefix(owner)
+        .range(
+            storage,
+            Some(Bound::inclusive(window_start(now))),
+            None,
+            Order::Ascending,
+        )
+        .map(|item| item.map(|(_, volume)| volume))
+        .sum()
+}
+
+/// Highest tier, and its index, whose minimum volume `volume` reaches. An
+/// empty schedule charges no fees.
+pub fn fee_tier(tiers: &[FeeTier], volume: Uint128) -> (usize, FeeTier) {
+    tiers
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, tier)| volume >= tier.min_volume)
+        .map(|(index, tier)| (index, tier.clone()))
+        .unwrap_or_default()
+}
+
+/// Share of `notional` taken at `fee_bps` basis points.
+pub fn fee_amount(notional: Uint128, fee_bps: u64) -> Uint128 {
+    notional.multiply_ratio(fee_bps, 10_000u64)
+}
+
+/// Credits the referrer of `taker`, if any, with `share_bps` of its taker fee
+/// on a fill, at most `available` of it. Returns the reward.
+pub fn pay_referral(
+    storage: &mut dyn Storage,
+    taker: &Addr,
+    notional: Uint128,
+    taker_fee: Uint128,
+    available: Uint128,
+    share_bps: u64,
+) -> StdResult<Uint128> {
+    let referrer = match REFERRERS.may_load(storage, taker)? {
+        Some(referrer) => referrer,
+        None => return Ok(Uint128::zero()),
+    };
+    let reward = fee_amount(taker_fee, share_bps).min(available);
+    let mut referrals = REFERRALS.may_load(storage, &referrer)?.unwrap_or_default();
+    referrals.referred_volume += notional;
+    referrals.total_rewards += reward;
+    referrals.unclaimed_rewards += reward;
+    REFERRALS.save(storage, &referrer, &referrals)?;
+    Ok(reward)
+}
@@ -1,47 +1,93 @@
 #[cfg(test)]
 mod tests {
-    use crate::helpers::CwTemplateContract;
-    use crate::msg::InstantiateMsg;
-    use cosmwasm_std::{Addr, Coin, Empty, Uint128};
+    use crate::helpers::OrderbookContract;
+    use crate::msg::{
+        AccountFeesResponse, AdlRankingResponse, DelegatesResponse, ExecuteMsg, FundingResponse,
+        InstantiateMsg, InsuranceFundResponse, LastTradePriceResponse,
+        LiquidatablePositionsResponse, ListMarketsResponse, MarginAccountResponse,
+        OrderBookResponse, OrdersResponse, PositionResponse, PositionsResponse, QueryMsg,
+        ReferralRewardsResponse, ReferralStatsResponse, SubAccountsResponse, TimeInForce,
+        TradesResponse, TriggerOrdersResponse,
+    };
+    use crate::state::{Collateral, FeeTier, MarginMode, Side, TriggerKind};
+    use crate::ContractError;
+    use cosmwasm_std::{coins, Addr, Coin, Decimal, Empty, Int128, Uint128};
     use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
 
-    pub fn contract_template() -> Box<dyn Contract<Empty>> {
+    pub fn orderbook_contract() -> Box<dyn Contract<Empty>> {
         let contract = ContractWrapper::new(
             crate::contract::execute,
             crate::contract::instantiate,
             crate::contract::query,
+        )
+        .with_reply(crate::contract::reply);
+        Box::new(contract)
+    }
+
+    pub fn oracle_contract() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            oracle::contract::execute,
+            oracle::contract::instantiate,
+            oracle::contract::query,
         );
         Box::new(contract)
     }
 
-    const USER: &str = "USER";
-    const ADMIN: &str = "ADMIN";
+    const USER: &str = "user";
+    const ADMIN: &str = "admin";
+    const KEEPER: &str = "keeper";
+    const TREASURY: &str = "treasury";
     const NATIVE_DENOM: &str = "denom";
 
     fn mock_app() -> App {
         AppBuilder::new().build(|router, _, storage| {
-            router
-                .bank
-                .init_balance(
-                    storage,
-                    &Addr::unchecked(USER),
-                    vec![Coin {
-                        denom: NATIVE_DENOM.to_string(),
-                        amount: Uint128::new(1),
-                    }],
-                )
-                .unwrap();
+            for addr in [USER, ADMIN] {
+                router
+                    .bank
+                    .init_balance(
+                        storage,
+                        &Addr::unchecked(addr),
+                        vec![Coin {
+                            denom: NATIVE_DENOM.to_string(),
+                            amount: Uint128::new(1000),
+                        }],
+                    )
+                    .unwrap();
+            }
         })
     }
 
-    fn proper_instantiate() -> (App, CwTemplateContract) {
+    fn proper_instantiate() -> (App, OrderbookContract, Addr) {
         let mut app = mock_app();
-        let cw_template_id = app.store_code(contract_template());
+        let oracle_id = app.store_code(oracle_contract());
+        let oracle_addr = app
+            .instantiate_contract(
+                oracle_id,
+                Addr::unchecked(ADMIN),
+                &oracle::msg::InstantiateMsg {
+                    owner: ADMIN.to_string(),
+                },
+                &[],
+                "oracle",
+                None,
+            )
+            .unwrap();
 
-        let msg = InstantiateMsg { count: 1i32 };
-        let cw_template_contract_addr = app
+        let orderbook_id = app.store_code(orderbook_contract());
+        let msg = InstantiateMsg {
+            owner: ADMIN.to_string(),
+            oracle: oracle_addr.to_string(),
+            collateral: Collateral::Native {
+                denom: NATIVE_DENOM.to_string(),
+            },
+            treasury: TREASURY.to_string(),
+            trigger_fee: Uint128::new(2),
+            max_batch_size: 20,
+            referral_share_bps: 0,
+        };
+        let orderbook_contract_addr = app
             .instantiate_contract(
-                cw_template_id,
+                orderbook_id,
                 Addr::unchecked(ADMIN),
                 &msg,
                 &[],
@@ -50,22 +96,2343 @@ mod tests {
             )
             .unwrap();
 
-        let cw_template_contract = CwTemplateContract(cw_template_contract_addr);
+        let orderbook_contract = OrderbookContract(orderbook_contract_addr);
+
+        (app, orderbook_contract, oracle_addr)
+    }
 
-        (app, cw_template_contract)
+    fn create_market_msg(base_asset: &str) -> ExecuteMsg {
+        ExecuteMsg::CreateMarket {
+            base_asset: base_asset.to_string(),
+            quote_asset: "usd".to_string(),
+            max_bet_size: Uint128::new(1_000_000),
+            tick_size: Uint128::new(1),
+            min_order_size: Uint128::new(1),
+            max_order_size: Uint128::new(1_000_000),
+            max_open_interest: Uint128::new(10_000_000),
+            max_funding_rate: Decimal::percent(1),
+            price_band_bps: None,
+            max_price_age: None,
+            expiry: None,
+        }
     }
 
-    mod count {
-        use super::*;
-        use crate::msg::ExecuteMsg;
+    fn create_market(app: &mut App, contract: &OrderbookContract, msg: ExecuteMsg) {
+        app.execute_contract(Addr::unchecked(ADMIN), contract.addr(), &msg, &[])
+            .unwrap();
+    }
+
+    fn query_list_markets(
+        app: &App,
+        contract: &OrderbookContract,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> ListMarketsResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::ListMarkets { start_after, limit },
+            )
+            .unwrap()
+    }
 
-        #[test]
-        fn count() {
-            let (mut app, cw_template_contract) = proper_instantiate();
+    #[test]
+    fn list_markets() {
+        let (mut app, orderbook_contract, _) = proper_instantiate();
 
-            let msg = ExecuteMsg::Increment {};
-            let cosmos_msg = cw_template_contract.call(msg).unwrap();
+        for base_asset in ["btc", "eth", "atom"] {
+            create_market(&mut app, &orderbook_contract, create_market_msg(base_asset));
+        }
+
+        let res = query_list_markets(&app, &orderbook_contract, None, Some(2));
+        assert_eq!(res.markets.len(), 2);
+        assert_eq!(res.markets[0].0, 0);
+        assert_eq!(res.markets[1].1.base_asset, "eth");
+
+        let res = query_list_markets(&app, &orderbook_contract, Some(1), None);
+        assert_eq!(res.markets.len(), 1);
+        assert_eq!(res.markets[0].0, 2);
+        assert_eq!(res.markets[0].1.base_asset, "atom");
+    }
+
+    fn query_orders_by_owner(
+        app: &App,
+        contract: &OrderbookContract,
+        owner: &str,
+    ) -> OrdersResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::OrdersByOwner {
+                    owner: owner.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn cancel_orders() {
+        let (mut app, orderbook_contract, _) = proper_instantiate();
+
+        for base_asset in ["btc", "eth"] {
+            create_market(&mut app, &orderbook_contract, create_market_msg(base_asset));
+        }
+        for market_id in [0, 1, 1] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id,
+                side: Side::Buy,
+                price: Uint128::new(10),
+                size: Uint128::new(5),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            let cosmos_msg = orderbook_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
         }
+        assert_eq!(
+            query_orders_by_owner(&app, &orderbook_contract, USER)
+                .orders
+                .len(),
+            3
+        );
+
+        // only the owner can cancel an order
+        let cosmos_msg = orderbook_contract
+            .call(ExecuteMsg::CancelOrder { order_id: 0 })
+            .unwrap();
+        app.execute(Addr::unchecked(ADMIN), cosmos_msg.clone())
+            .unwrap_err();
+        app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+
+        let cosmos_msg = orderbook_contract
+            .call(ExecuteMsg::CancelAll { market_id: Some(1) })
+            .unwrap();
+        app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+        let res = query_orders_by_owner(&app, &orderbook_contract, USER);
+        assert!(res.orders.is_empty());
+    }
+
+    fn set_price(app: &mut App, oracle_addr: &Addr, amount: u128) {
+        let msg = oracle::msg::ExecuteMsg::SetSinglePrice {
+            base_asset: "btc".to_string(),
+            quote_asset: "usd".to_string(),
+            amount: Uint128::new(amount),
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), oracle_addr.clone(), &msg, &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn positions_settle_at_oracle_price() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        for (addr, side) in [(USER, Side::Buy), (ADMIN, Side::Sell)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(100, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::OpenPosition {
+                market_id: 0,
+                side,
+                size: Uint128::new(1000),
+                collateral: Uint128::new(100),
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        set_price(&mut app, &oracle_addr, 1_050_000);
+        let res: PositionResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Position {
+                    owner: USER.to_string(),
+                    market_id: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.mark_price, Uint128::new(1_050_000));
+        assert_eq!(res.unrealized_pnl, Int128::new(50));
+
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(150));
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(150),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let balance = app.wrap().query_balance(USER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(1050));
+    }
+
+    fn query_margin_account(
+        app: &App,
+        contract: &OrderbookContract,
+        owner: &str,
+    ) -> MarginAccountResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::MarginAccount {
+                    owner: owner.to_string(),
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn margin_account_backs_orders_and_fills() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        for (addr, side) in [(USER, Side::Buy), (ADMIN, Side::Sell)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // the fill posted the reserved margin to a long position
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(500));
+        assert_eq!(res.used_margin, Uint128::new(500));
+        assert_eq!(res.maintenance_margin, Uint128::new(500));
+        assert_eq!(res.margin_ratio, Some(Decimal::percent(200)));
+
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(501),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(500),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let balance = app.wrap().query_balance(USER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(500));
+
+        set_price(&mut app, &oracle_addr, 1_100_000);
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.unrealized_pnl, Int128::new(1000));
+        assert_eq!(res.equity, Uint128::new(1500));
+    }
+
+    #[test]
+    fn liquidate_undercollateralized_account() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        // the user opens a long with all of its collateral at maximum leverage
+        for (addr, side, deposit) in [(USER, Side::Buy, 500), (ADMIN, Side::Sell, 1000)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(deposit, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        let liquidate = ExecuteMsg::Liquidate {
+            owner: USER.to_string(),
+            market_id: 0,
+        };
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &liquidate,
+            &[],
+        )
+        .unwrap_err();
+
+        set_price(&mut app, &oracle_addr, 990_000);
+        let res: LiquidatablePositionsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::LiquidatablePositions {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.positions.len(), 1);
+        assert_eq!(res.positions[0].owner, USER);
+        assert_eq!(res.next_start_after, None);
+
+        // half is closed, the keeper earns 1% of it and the rest is insured
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &liquidate,
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(KEEPER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(49));
+        let res: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(orderbook_contract.addr(), &QueryMsg::InsuranceFund {})
+            .unwrap();
+        assert_eq!(res.balance, Uint128::new(151));
+
+        // a crash closes the rest: the fund pays the keeper's minimum bonus
+        // of 20 and absorbs what it can, and the admin's short is deleveraged
+        // to cover the remaining 619
+        set_price(&mut app, &oracle_addr, 800_000);
+        let res = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &liquidate,
+                &[],
+            )
+            .unwrap();
+        let wasm = res.events.iter().find(|ev| ev.ty == "wasm").unwrap();
+        let bad_debt = wasm.attributes.iter().find(|attr| attr.key == "bad_debt");
+        assert_eq!(bad_debt.unwrap().value, "0");
+        assert!(res.events.iter().any(|ev| ev.ty == "wasm-adl"));
+        let balance = app.wrap().query_balance(KEEPER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(69));
+        let res: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(orderbook_contract.addr(), &QueryMsg::InsuranceFund {})
+            .unwrap();
+        assert_eq!(res.balance, Uint128::zero());
+        let res: PositionsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::PositionsByOwner {
+                    owner: USER.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert!(res.positions.is_empty());
+    }
+
+    #[test]
+    fn stale_prices_do_not_liquidate() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket { max_price_age, .. } = &mut msg {
+            *max_price_age = Some(600);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        for (addr, side) in [(USER, Side::Buy), (ADMIN, Side::Sell)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(500, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // the long is underwater at the last price, which has gone stale
+        set_price(&mut app, &oracle_addr, 990_000);
+        app.update_block(|block| block.time = block.time.plus_seconds(601));
+        let liquidate = ExecuteMsg::Liquidate {
+            owner: USER.to_string(),
+            market_id: 0,
+        };
+        let err = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &liquidate,
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::StalePrice { .. }
+        ));
+        set_price(&mut app, &oracle_addr, 990_000);
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &liquidate,
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn funding_accrues_to_positions() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        // a long and a short are opened, then quotes are left well above the index
+        for addr in [USER, ADMIN] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        for (addr, side, price, size) in [
+            (USER, Side::Buy, 1_000_000, 10_000),
+            (ADMIN, Side::Sell, 1_000_000, 10_000),
+            (USER, Side::Buy, 1_040_000, 100),
+            (ADMIN, Side::Sell, 1_060_000, 100),
+        ] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(price),
+                size: Uint128::new(size),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // the 5% premium is capped at 1% of the index per interval
+        let settle = ExecuteMsg::SettleFunding { market_id: 0 };
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &settle,
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &settle,
+            &[],
+        )
+        .unwrap_err();
+        app.update_block(|block| block.time = block.time.plus_seconds(60 * 60));
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &settle,
+            &[],
+        )
+        .unwrap();
+
+        let res: FundingResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Funding { market_id: 0 },
+            )
+            .unwrap();
+        assert_eq!(res.funding.cumulative_index, Int128::new(20_000));
+        for (addr, pending) in [(USER, 200), (ADMIN, -200)] {
+            let res: PositionResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    orderbook_contract.addr(),
+                    &QueryMsg::Position {
+                        owner: addr.to_string(),
+                        market_id: 0,
+                    },
+                )
+                .unwrap();
+            assert_eq!(res.pending_funding, Int128::new(pending));
+        }
+
+        // closing settles the funding out of the long's collateral
+        let before = query_margin_account(&app, &orderbook_contract, USER).free_collateral;
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral - before, Uint128::new(300));
+    }
+
+    fn query_trigger_orders(
+        app: &App,
+        contract: &OrderbookContract,
+        owner: &str,
+    ) -> TriggerOrdersResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::TriggerOrdersByOwner {
+                    owner: owner.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn stop_loss_triggers_at_mark_price() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        for (addr, side) in [(ADMIN, Side::Sell), (USER, Side::Buy)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // the user protects the long on both sides, reserving a fee for each
+        for (kind, trigger_price) in [
+            (TriggerKind::StopLoss, 980_000),
+            (TriggerKind::TakeProfit, 1_200_000),
+        ] {
+            let msg = ExecuteMsg::PlaceTriggerOrder {
+                market_id: 0,
+                side: Side::Sell,
+                kind,
+                trigger_price: Uint128::new(trigger_price),
+                size: Uint128::new(10_000),
+            };
+            app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+        let res = query_trigger_orders(&app, &orderbook_contract, USER);
+        assert_eq!(res.trigger_orders.len(), 2);
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(496));
+
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(980_000),
+            size: Uint128::new(10_000),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // nothing triggers until the mark price reaches the stop
+        let msg = ExecuteMsg::ExecuteTriggers {
+            market_id: 0,
+            limit: None,
+        };
+        let res = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &msg,
+                &[],
+            )
+            .unwrap();
+        let wasm = res.events.iter().find(|ev| ev.ty == "wasm").unwrap();
+        assert!(wasm
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "triggered" && attr.value == "0"));
+
+        set_price(&mut app, &oracle_addr, 980_000);
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &msg,
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(KEEPER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(2));
+
+        // the stop closed the long against the resting bid at a 200 loss
+        let res: PositionsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::PositionsByOwner {
+                    owner: USER.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert!(res.positions.is_empty());
+
+        let res = query_trigger_orders(&app, &orderbook_contract, USER);
+        let (trigger_id, _) = res.trigger_orders[0];
+        let msg = ExecuteMsg::CancelTriggerOrder { trigger_id };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res = query_trigger_orders(&app, &orderbook_contract, USER);
+        assert!(res.trigger_orders.is_empty());
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(798));
+    }
+
+    #[test]
+    fn failing_trigger_is_dropped_without_blocking_the_crank() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket {
+            max_bet_size,
+            max_open_interest,
+            ..
+        } = &mut msg
+        {
+            *max_bet_size = Uint128::new(10_000);
+            *max_open_interest = Uint128::new(10_000);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        const CAROL: &str = "carol";
+        const DAVE: &str = "dave";
+        for addr in [CAROL, DAVE] {
+            app.send_tokens(
+                Addr::unchecked(ADMIN),
+                Addr::unchecked(addr),
+                &coins(300, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        for (addr, deposit) in [(ADMIN, 400), (USER, 1000), (CAROL, 300), (DAVE, 300)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(deposit, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+
+        // the user goes long 6k against the admin, and dave rests a 5k ask
+        for (addr, side, size) in [
+            (ADMIN, Side::Sell, 6_000),
+            (USER, Side::Buy, 6_000),
+            (DAVE, Side::Sell, 5_000),
+        ] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(size),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // carol's stop only fails once it has matched and breaches the cap
+        for (addr, size) in [(CAROL, 5_000), (USER, 2_000)] {
+            let msg = ExecuteMsg::PlaceTriggerOrder {
+                market_id: 0,
+                side: Side::Buy,
+                kind: TriggerKind::StopLoss,
+                trigger_price: Uint128::new(1_000_000),
+                size: Uint128::new(size),
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        let msg = ExecuteMsg::ExecuteTriggers {
+            market_id: 0,
+            limit: None,
+        };
+        let res = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &msg,
+                &[],
+            )
+            .unwrap();
+        assert!(res.events.iter().any(|ev| ev
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "method" && attr.value == "drop_trigger")));
+
+        // carol's stop is cancelled with its fee refunded, the user's fills
+        for addr in [CAROL, USER] {
+            let res = query_trigger_orders(&app, &orderbook_contract, addr);
+            assert!(res.trigger_orders.is_empty());
+        }
+        let res = query_margin_account(&app, &orderbook_contract, CAROL);
+        assert_eq!(res.free_collateral, Uint128::new(300));
+        let balance = app.wrap().query_balance(KEEPER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(2));
+        let res: PositionResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Position {
+                    owner: USER.to_string(),
+                    market_id: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.position.size, Int128::new(8_000));
+
+        // only the contract itself can execute a single trigger
+        let msg = ExecuteMsg::ExecuteTrigger {
+            trigger_id: 0,
+            keeper: KEEPER.to_string(),
+            mark_price: Uint128::new(1_000_000),
+        };
+        let err = app
+            .execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::Unauthorized {}
+        ));
+    }
+
+    fn query_account_fees(
+        app: &App,
+        contract: &OrderbookContract,
+        owner: &str,
+    ) -> AccountFeesResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::AccountFees {
+                    owner: owner.to_string(),
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn fees_follow_volume_tiers() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        // rebates larger than the lowest taker fee are rejected
+        let tier = |min_volume: u128, maker_fee_bps: i64, taker_fee_bps: u64| FeeTier {
+            min_volume: Uint128::new(min_volume),
+            maker_fee_bps,
+            taker_fee_bps,
+        };
+        let msg = ExecuteMsg::UpdateFeeTiers {
+            tiers: vec![tier(0, 2, 10), tier(10_000, -20, 5)],
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        let msg = ExecuteMsg::UpdateFeeTiers {
+            tiers: vec![tier(0, 2, 10), tier(10_000, -2, 5)],
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        for addr in [ADMIN, USER] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        let trade = |app: &mut App, maker: &str, taker: &str, side: Side| {
+            for (addr, side) in [(maker, side.opposite()), (taker, side)] {
+                let msg = ExecuteMsg::PlaceLimitOrder {
+                    market_id: 0,
+                    side,
+                    price: Uint128::new(1_000_000),
+                    size: Uint128::new(10_000),
+                    post_only: false,
+                    expires: None,
+                    reduce_only: false,
+                };
+                app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                    .unwrap();
+            }
+        };
+
+        // both start in the base tier: the user pays 10 as taker, the admin 2
+        // as maker, and the 10,000 traded moves both up a tier
+        trade(&mut app, ADMIN, USER, Side::Buy);
+        let res = query_account_fees(&app, &orderbook_contract, USER);
+        assert_eq!(res.volume, Uint128::new(10_000));
+        assert_eq!(res.tier, 1);
+        assert_eq!(res.maker_fee_bps, -2);
+
+        // the user closes as a maker, earning a rebate of 2 out of the
+        // admin's taker fee of 5
+        trade(&mut app, USER, ADMIN, Side::Buy);
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(992));
+
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &ExecuteMsg::SweepFees {},
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(TREASURY, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(15));
+
+        // volume older than the window no longer counts
+        app.update_block(|block| block.time = block.time.plus_seconds(31 * 24 * 60 * 60));
+        let res = query_account_fees(&app, &orderbook_contract, USER);
+        assert_eq!(res.volume, Uint128::zero());
+        assert_eq!(res.tier, 0);
+    }
+
+    #[test]
+    fn isolated_positions_stand_alone() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        let msg = ExecuteMsg::SetMarginMode {
+            mode: MarginMode::Isolated,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        for (addr, side) in [(ADMIN, Side::Sell), (USER, Side::Buy)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+        // the mode is locked while a position is open
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+
+        // collateral moves in and out, down to the initial margin
+        let msg = ExecuteMsg::AddCollateral {
+            market_id: 0,
+            amount: Uint128::new(100),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let msg = ExecuteMsg::RemoveCollateral {
+            market_id: 0,
+            amount: Uint128::new(200),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        let msg = ExecuteMsg::RemoveCollateral {
+            market_id: 0,
+            amount: Uint128::new(100),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // the position is underwater on its own 500, even though the account
+        // still holds 500 of free collateral that remains withdrawable
+        set_price(&mut app, &oracle_addr, 955_000);
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.mode, MarginMode::Isolated);
+        assert_eq!(res.margin_ratio, Some(Decimal::from_ratio(50u128, 477u128)));
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(400),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // the liquidator's bonus is limited to what the position leaves
+        let msg = ExecuteMsg::Liquidate {
+            owner: USER.to_string(),
+            market_id: 0,
+        };
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &msg,
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(KEEPER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(50));
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(100));
+        assert_eq!(res.margin_ratio, None);
+    }
+
+    #[test]
+    fn bad_debt_deleverages_profitable_positions() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        for (addr, side, deposit) in [(ADMIN, Side::Sell, 1000), (USER, Side::Buy, 500)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(deposit, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // a 10% drop leaves the long 500 short of its collateral with nothing
+        // in the insurance fund, while the admin's short is up 1000
+        set_price(&mut app, &oracle_addr, 900_000);
+        let res: AdlRankingResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::AdlRanking {
+                    market_id: 0,
+                    side: Side::Sell,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.positions.len(), 1);
+        assert_eq!(res.positions[0].owner, ADMIN);
+        assert_eq!(res.positions[0].unrealized_pnl, Int128::new(1000));
+        assert_eq!(res.positions[0].score, Decimal::from_ratio(36u128, 1u128));
+
+        let msg = ExecuteMsg::Liquidate {
+            owner: USER.to_string(),
+            market_id: 0,
+        };
+        let res = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &msg,
+                &[],
+            )
+            .unwrap();
+        let adl = res.events.iter().find(|ev| ev.ty == "wasm-adl").unwrap();
+        let price = adl
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "price")
+            .unwrap();
+        assert_eq!(price.value, "950000");
+        let wasm = res.events.iter().find(|ev| ev.ty == "wasm").unwrap();
+        assert!(wasm
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "bad_debt" && attr.value == "0"));
+
+        // the short closed at the bankruptcy price, giving up 500 of its profit
+        let res = query_margin_account(&app, &orderbook_contract, ADMIN);
+        assert_eq!(res.free_collateral, Uint128::new(1500));
+        assert_eq!(res.used_margin, Uint128::zero());
+    }
+
+    #[test]
+    fn dated_market_settles_at_twap() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let expiry = app.block_info().time.seconds() + 2 * 60 * 60;
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket { expiry: dated, .. } = &mut msg {
+            *dated = Some(expiry);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        for (addr, side) in [(USER, Side::Buy), (ADMIN, Side::Sell)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(100, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::OpenPosition {
+                market_id: 0,
+                side,
+                size: Uint128::new(1000),
+                collateral: Uint128::new(100),
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+
+        // a halted market freezes positions until its owner resumes it
+        let msg = ExecuteMsg::HaltMarket { market_id: 0 };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let close = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &close,
+            &[],
+        )
+        .unwrap_err();
+        let msg = ExecuteMsg::ResumeMarket { market_id: 0 };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        let settle = ExecuteMsg::SettlePosition { market_id: 0 };
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &settle,
+            &[],
+        )
+        .unwrap_err();
+
+        // the last hour before expiry spends half its time at each price, and
+        // prices set after expiry are ignored
+        app.update_block(|block| block.time = block.time.plus_seconds(90 * 60));
+        set_price(&mut app, &oracle_addr, 1_100_000);
+        app.update_block(|block| block.time = block.time.plus_seconds(40 * 60));
+        set_price(&mut app, &oracle_addr, 2_000_000);
+
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &close,
+            &[],
+        )
+        .unwrap_err();
+        let res = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &settle,
+                &[],
+            )
+            .unwrap();
+        let wasm = res.events.iter().find(|ev| ev.ty == "wasm").unwrap();
+        assert!(wasm
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "settlement_price" && attr.value == "1050000"));
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(150));
+
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &settle,
+            &[],
+        )
+        .unwrap();
+        let res = query_margin_account(&app, &orderbook_contract, ADMIN);
+        assert_eq!(res.free_collateral, Uint128::new(50));
+    }
+
+    #[test]
+    fn collateral_stays_on_frozen_positions() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let expiry = app.block_info().time.seconds() + 2 * 60 * 60;
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket {
+            max_price_age,
+            expiry: dated,
+            ..
+        } = &mut msg
+        {
+            *max_price_age = Some(600);
+            *dated = Some(expiry);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        let msg = ExecuteMsg::SetMarginMode {
+            mode: MarginMode::Isolated,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &ExecuteMsg::Deposit {
+                referral_code: None,
+            },
+            &coins(200, NATIVE_DENOM),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::OpenPosition {
+            market_id: 0,
+            side: Side::Buy,
+            size: Uint128::new(1000),
+            collateral: Uint128::new(200),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        let remove = ExecuteMsg::RemoveCollateral {
+            market_id: 0,
+            amount: Uint128::new(10),
+        };
+        let remove_err = |app: &mut App| {
+            app.execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &remove,
+                &[],
+            )
+            .unwrap_err()
+            .downcast::<ContractError>()
+            .unwrap()
+        };
+
+        // nothing leaves a halted market, and nothing is liquidated there
+        let msg = ExecuteMsg::HaltMarket { market_id: 0 };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        assert!(matches!(
+            remove_err(&mut app),
+            ContractError::MarketHalted { market_id: 0 }
+        ));
+        let liquidate = ExecuteMsg::Liquidate {
+            owner: USER.to_string(),
+            market_id: 0,
+        };
+        set_price(&mut app, &oracle_addr, 800_000);
+        let err = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &liquidate,
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::MarketHalted { market_id: 0 }
+        ));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        let msg = ExecuteMsg::ResumeMarket { market_id: 0 };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // nor is the position valued at a stale price
+        app.update_block(|block| block.time = block.time.plus_seconds(601));
+        assert!(matches!(
+            remove_err(&mut app),
+            ContractError::StalePrice { .. }
+        ));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &remove,
+            &[],
+        )
+        .unwrap();
+
+        // after expiry the collateral waits for settlement at the TWAP
+        app.update_block(|block| block.time = block.time.plus_seconds(2 * 60 * 60));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        assert!(matches!(
+            remove_err(&mut app),
+            ContractError::MarketExpired { market_id: 0 }
+        ));
+        let err = app
+            .execute_contract(
+                Addr::unchecked(KEEPER),
+                orderbook_contract.addr(),
+                &liquidate,
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::MarketExpired { market_id: 0 }
+        ));
+    }
+
+    #[test]
+    fn own_resting_orders_are_cancelled_not_traded() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        let msg = ExecuteMsg::CreateSubAccount {};
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let deposit = |sub_account: u32| ExecuteMsg::WithAccount {
+            owner: None,
+            sub_account,
+            msg: Box::new(ExecuteMsg::Deposit {
+                referral_code: None,
+            }),
+        };
+        for sub_account in [0, 1] {
+            let msg = match sub_account {
+                0 => ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                _ => deposit(sub_account),
+            };
+            app.execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &msg,
+                &coins(500, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+
+        // the wallet rests an ask, then bids across it from its sub-account
+        let order = |side: Side| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side,
+            price: Uint128::new(1_000_000),
+            size: Uint128::new(1000),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &order(Side::Sell),
+            &[],
+        )
+        .unwrap();
+        let msg = ExecuteMsg::WithAccount {
+            owner: None,
+            sub_account: 1,
+            msg: Box::new(order(Side::Buy)),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // no trade: the ask is cancelled with its margin refunded and the bid
+        // rests in its place
+        let res: TradesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::TradesByMarket {
+                    market_id: 0,
+                    start_after: None,
+                    start_time: None,
+                    end_time: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert!(res.trades.is_empty());
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(500));
+        let res: OrderBookResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::OrderBook {
+                    market_id: 0,
+                    depth: 10,
+                },
+            )
+            .unwrap();
+        assert!(res.asks.is_empty());
+        assert_eq!(res.bids.len(), 1);
+
+        // nor does the wallet's own bid count as liquidity for a market order
+        let msg = ExecuteMsg::PlaceMarketOrder {
+            market_id: 0,
+            side: Side::Sell,
+            size: Uint128::new(1000),
+            max_slippage_bps: 100,
+            time_in_force: TimeInForce::Ioc,
+            reduce_only: false,
+        };
+        let err = app
+            .execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::InsufficientLiquidity { .. }
+        ));
+    }
+
+    #[test]
+    fn delegates_trade_sub_accounts() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+
+        let msg = ExecuteMsg::CreateSubAccount {};
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let msg = ExecuteMsg::AddDelegate {
+            sub_account: 1,
+            delegate: KEEPER.to_string(),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res: SubAccountsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::SubAccounts {
+                    owner: USER.to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.sub_accounts.len(), 1);
+        let account = res.sub_accounts[0].account.clone();
+        assert_eq!(account, format!("{}/1", USER));
+
+        // account queries take sub-account addresses as well
+        let res: SubAccountsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::SubAccounts {
+                    owner: account.clone(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert!(res.sub_accounts.is_empty());
+        let res: ReferralRewardsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::ReferralRewards {
+                    owner: account.clone(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.unclaimed, Uint128::zero());
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Delegates {
+                    owner: USER.to_string(),
+                    sub_account: 1,
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.delegates, vec![KEEPER.to_string()]);
+
+        let msg = ExecuteMsg::WithAccount {
+            owner: None,
+            sub_account: 1,
+            msg: Box::new(ExecuteMsg::Deposit {
+                referral_code: None,
+            }),
+        };
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &msg,
+            &coins(500, NATIVE_DENOM),
+        )
+        .unwrap();
+
+        // the delegate trades the sub-account, which only its owner can fund
+        let place = ExecuteMsg::WithAccount {
+            owner: Some(USER.to_string()),
+            sub_account: 1,
+            msg: Box::new(ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side: Side::Buy,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(1000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            }),
+        };
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &place,
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &place,
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &ExecuteMsg::Deposit {
+                referral_code: None,
+            },
+            &coins(500, NATIVE_DENOM),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Sell,
+            price: Uint128::new(1_000_000),
+            size: Uint128::new(1000),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res: PositionResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Position {
+                    owner: account.clone(),
+                    market_id: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.position.size, Int128::new(1000));
+
+        let withdraw = |owner: Option<&str>| ExecuteMsg::WithAccount {
+            owner: owner.map(str::to_string),
+            sub_account: 1,
+            msg: Box::new(ExecuteMsg::Withdraw {
+                amount: Uint128::new(400),
+            }),
+        };
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &withdraw(Some(USER)),
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &withdraw(None),
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(USER, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(900));
+        let res = query_margin_account(&app, &orderbook_contract, &account);
+        assert_eq!(res.free_collateral, Uint128::new(50));
+
+        // a removed delegate loses access
+        let msg = ExecuteMsg::RemoveDelegate {
+            sub_account: 1,
+            delegate: KEEPER.to_string(),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &place,
+            &[],
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn fills_are_recorded_as_trades() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        for addr in [ADMIN, USER] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(500, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+
+        // the admin makes both trades; the user takes them an hour apart
+        let first_trade = app.block_info().time.seconds();
+        for price in [1_000_000, 1_010_000] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side: Side::Sell,
+                price: Uint128::new(price),
+                size: Uint128::new(1000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side: Side::Buy,
+                price: Uint128::new(price),
+                size: Uint128::new(1000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            let res = app
+                .execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+            let fill = res.events.iter().find(|ev| ev.ty == "wasm-fill").unwrap();
+            assert!(fill
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "maker" && attr.value == ADMIN));
+            app.update_block(|block| {
+                block.height += 600;
+                block.time = block.time.plus_seconds(60 * 60);
+            });
+        }
+
+        let trades = |app: &App, msg: &QueryMsg| -> TradesResponse {
+            app.wrap()
+                .query_wasm_smart(orderbook_contract.addr(), msg)
+                .unwrap()
+        };
+        let res = trades(
+            &app,
+            &QueryMsg::TradesByMarket {
+                market_id: 0,
+                start_after: None,
+                start_time: None,
+                end_time: None,
+                limit: None,
+            },
+        );
+        assert_eq!(res.trades.len(), 2);
+        let (trade_id, trade) = &res.trades[0];
+        assert_eq!(*trade_id, 1);
+        assert_eq!(trade.maker, ADMIN);
+        assert_eq!(trade.taker, USER);
+        assert_eq!(trade.side, Side::Buy);
+        assert_eq!(trade.price, Uint128::new(1_010_000));
+        assert_eq!(trade.size, Uint128::new(1000));
+
+        // pages go back in time, and time filters pick out the first trade
+        let res = trades(
+            &app,
+            &QueryMsg::TradesByAccount {
+                owner: USER.to_string(),
+                start_after: Some(1),
+                start_time: None,
+                end_time: None,
+                limit: None,
+            },
+        );
+        assert_eq!(res.trades.len(), 1);
+        assert_eq!(res.trades[0].0, 0);
+        let res = trades(
+            &app,
+            &QueryMsg::TradesByAccount {
+                owner: ADMIN.to_string(),
+                start_after: None,
+                start_time: Some(first_trade),
+                end_time: Some(first_trade + 60),
+                limit: None,
+            },
+        );
+        assert_eq!(res.trades.len(), 1);
+        assert_eq!(res.trades[0].1.timestamp, first_trade);
+
+        let res: LastTradePriceResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::LastTradePrice { market_id: 0 },
+            )
+            .unwrap();
+        assert_eq!(res.price, Some(Uint128::new(1_010_000)));
+        assert_eq!(res.timestamp, Some(first_trade + 60 * 60));
+    }
+
+    #[test]
+    fn limit_prices_stay_within_the_oracle_band() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket {
+            price_band_bps,
+            max_price_age,
+            ..
+        } = &mut msg
+        {
+            *price_band_bps = Some(500);
+            *max_price_age = Some(600);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &ExecuteMsg::Deposit {
+                referral_code: None,
+            },
+            &coins(500, NATIVE_DENOM),
+        )
+        .unwrap();
+
+        let bid = |price: u128| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(price),
+            size: Uint128::new(100),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &bid(940_000),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap().to_string(),
+            "Price 940000 is outside the band of 950000 to 1050000 around the mark price 1000000"
+        );
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &bid(950_000),
+            &[],
+        )
+        .unwrap();
+
+        // once the oracle falls behind, nothing can be placed until it updates
+        app.update_block(|block| block.time = block.time.plus_seconds(601));
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &bid(1_000_000),
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::StalePrice {
+                max_price_age: 600,
+                ..
+            }
+        ));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &bid(1_000_000),
+            &[],
+        )
+        .unwrap();
+
+        // a triggered stop limit outside the band is dropped, not rested
+        let before = query_margin_account(&app, &orderbook_contract, USER).free_collateral;
+        let msg = ExecuteMsg::PlaceTriggerOrder {
+            market_id: 0,
+            side: Side::Buy,
+            kind: TriggerKind::StopLimit {
+                limit_price: Uint128::new(1_060_000),
+            },
+            trigger_price: Uint128::new(1_000_000),
+            size: Uint128::new(100),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let msg = ExecuteMsg::ExecuteTriggers {
+            market_id: 0,
+            limit: None,
+        };
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &msg,
+            &[],
+        )
+        .unwrap();
+        let res = query_trigger_orders(&app, &orderbook_contract, USER);
+        assert!(res.trigger_orders.is_empty());
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, before);
+
+        // the owner can lift both the band and the staleness limit
+        let msg = ExecuteMsg::UpdateMarket {
+            market_id: 0,
+            max_bet_size: None,
+            tick_size: None,
+            min_order_size: None,
+            max_order_size: None,
+            max_open_interest: None,
+            max_funding_rate: None,
+            price_band_bps: Some(0),
+            max_price_age: Some(0),
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        app.update_block(|block| block.time = block.time.plus_seconds(601));
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &bid(940_000),
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn referrers_earn_a_share_of_taker_fees() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        let msg = ExecuteMsg::UpdateFeeTiers {
+            tiers: vec![FeeTier {
+                min_volume: Uint128::zero(),
+                maker_fee_bps: 2,
+                taker_fee_bps: 10,
+            }],
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let msg = ExecuteMsg::SetReferralShare {
+            referral_share_bps: 10_001,
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+        let msg = ExecuteMsg::SetReferralShare {
+            referral_share_bps: 5_000,
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+
+        // codes are short, lower case and unique, one per account
+        let register = |app: &mut App, addr: &str, code: &str| {
+            let msg = ExecuteMsg::RegisterReferralCode {
+                code: code.to_string(),
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+        };
+        let err = register(&mut app, ADMIN, "Desk").unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::InvalidReferralCode {}
+        ));
+        register(&mut app, ADMIN, "desk").unwrap();
+        let err = register(&mut app, USER, "desk").unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap().to_string(),
+            "Referral code desk is already taken"
+        );
+        register(&mut app, ADMIN, "desk-2").unwrap_err();
+
+        let deposit = |app: &mut App, addr: &str, code: &str, amount: u128| {
+            let msg = ExecuteMsg::Deposit {
+                referral_code: Some(code.to_string()),
+            };
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &msg,
+                &coins(amount, NATIVE_DENOM),
+            )
+        };
+        let err = deposit(&mut app, ADMIN, "desk", 1000).unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::SelfReferral {}
+        ));
+        deposit(&mut app, USER, "nobody", 500).unwrap_err();
+        deposit(&mut app, USER, "desk", 500).unwrap();
+        // only the first deposit binds a referrer
+        deposit(&mut app, USER, "nobody", 500).unwrap();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &ExecuteMsg::Deposit {
+                referral_code: None,
+            },
+            &coins(1000, NATIVE_DENOM),
+        )
+        .unwrap();
+
+        // the user takes 10,000 and pays 10, half of which goes to the admin
+        for (addr, side) in [(ADMIN, Side::Sell), (USER, Side::Buy)] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+        let res: ReferralStatsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::ReferralStats {
+                    owner: ADMIN.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            res,
+            ReferralStatsResponse {
+                code: Some("desk".to_string()),
+                referrer: None,
+                referred_accounts: 1,
+                referred_volume: Uint128::new(10_000),
+                total_rewards: Uint128::new(5),
+            }
+        );
+        let res: ReferralStatsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::ReferralStats {
+                    owner: USER.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.referrer, Some(ADMIN.to_string()));
+
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &ExecuteMsg::ClaimReferralRewards {},
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(ADMIN, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(5));
+        let res: ReferralRewardsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::ReferralRewards {
+                    owner: ADMIN.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.unclaimed, Uint128::zero());
+        assert_eq!(res.claimed, Uint128::new(5));
+
+        // the treasury keeps the rest of the fees
+        app.execute_contract(
+            Addr::unchecked(KEEPER),
+            orderbook_contract.addr(),
+            &ExecuteMsg::SweepFees {},
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(TREASURY, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(7));
+    }
+
+    #[test]
+    fn reduce_only_orders_never_flip_positions() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        for addr in [ADMIN, USER] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        let place =
+            |app: &mut App, addr: &str, side: Side, price: u128, size: u128, reduce_only| {
+                let msg = ExecuteMsg::PlaceLimitOrder {
+                    market_id: 0,
+                    side,
+                    price: Uint128::new(price),
+                    size: Uint128::new(size),
+                    post_only: false,
+                    expires: None,
+                    reduce_only,
+                };
+                app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+            };
+        let position = |app: &App, addr: &str| {
+            app.wrap()
+                .query_wasm_smart::<PositionResponse>(
+                    orderbook_contract.addr(),
+                    &QueryMsg::Position {
+                        owner: addr.to_string(),
+                        market_id: 0,
+                    },
+                )
+                .map(|res| res.position)
+        };
+
+        // without a position there is nothing to reduce
+        let err = place(&mut app, USER, Side::Sell, 1_000_000, 5_000, true).unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::NothingToReduce { market_id: 0 }
+        ));
+
+        // the user goes long 8,000 and rests two reduce-only asks of 5,000
+        place(&mut app, ADMIN, Side::Sell, 1_000_000, 8_000, false).unwrap();
+        place(&mut app, USER, Side::Buy, 1_000_000, 8_000, false).unwrap();
+        place(&mut app, USER, Side::Sell, 1_000_000, 5_000, true).unwrap();
+        place(&mut app, USER, Side::Sell, 1_000_000, 5_000, true).unwrap();
+
+        // a bid for 12,000 fills the first ask and only 3,000 of the second,
+        // which is then cancelled and its margin refunded, leaving the user
+        // flat rather than short
+        place(&mut app, ADMIN, Side::Buy, 1_000_000, 12_000, false).unwrap();
+        position(&app, USER).unwrap_err();
+        assert!(query_orders_by_owner(&app, &orderbook_contract, USER)
+            .orders
+            .is_empty());
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(1000));
+
+        // a plain order flips a short of 4,000 into a long of 6,000: the short
+        // realizes 400 at the fill price of 0.9 and the long opens there with
+        // 6/10 of the margin posted for the fill
+        place(&mut app, USER, Side::Sell, 1_000_000, 4_000, false).unwrap();
+        place(&mut app, ADMIN, Side::Sell, 900_000, 10_000, false).unwrap();
+        place(&mut app, USER, Side::Buy, 900_000, 10_000, false).unwrap();
+        let res = position(&app, USER).unwrap();
+        assert_eq!(res.size, Int128::new(6_000));
+        assert_eq!(res.entry_price, Uint128::new(900_000));
+        assert_eq!(res.collateral, Uint128::new(270));
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral + res.used_margin, Uint128::new(1400));
+
+        // a reduce-only ask is clamped to the long when placed
+        place(&mut app, USER, Side::Sell, 1_000_000, 10_000, true).unwrap();
+        let res = query_orders_by_owner(&app, &orderbook_contract, USER);
+        assert_eq!(res.orders[0].1.size, Uint128::new(6_000));
+        assert!(res.orders[0].1.reduce_only);
+    }
+
+    #[test]
+    fn closing_at_a_loss_draws_on_free_collateral() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        for addr in [ADMIN, USER] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(1000, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        // the user posts 500 of margin for a long of 10,000 and keeps 500 free
+        for (addr, side) in [(ADMIN, Side::Sell), (USER, Side::Buy)] {
+            let msg = ExecuteMsg::PlaceLimitOrder {
+                market_id: 0,
+                side,
+                price: Uint128::new(1_000_000),
+                size: Uint128::new(10_000),
+                post_only: false,
+                expires: None,
+                reduce_only: false,
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(500));
+
+        // a loss of 1,000 takes the position's 500 and the free 500 with it
+        set_price(&mut app, &oracle_addr, 900_000);
+        let close = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        for addr in [USER, ADMIN] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &close,
+                &[],
+            )
+            .unwrap();
+        }
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::zero());
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(1),
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap_err();
+
+        // the short's gain is fully backed by what the contract holds
+        let res = query_margin_account(&app, &orderbook_contract, ADMIN);
+        assert_eq!(res.free_collateral, Uint128::new(2000));
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(2000),
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let balance = app
+            .wrap()
+            .query_balance(orderbook_contract.addr(), NATIVE_DENOM)
+            .unwrap();
+        assert_eq!(balance.amount, Uint128::zero());
+    }
+
+    #[test]
+    fn isolated_fills_lose_at_most_the_position_collateral() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        create_market(&mut app, &orderbook_contract, create_market_msg("btc"));
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        let msg = ExecuteMsg::SetMarginMode {
+            mode: MarginMode::Isolated,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        for addr in [ADMIN, USER] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(600, NATIVE_DENOM),
+            )
+            .unwrap();
+        }
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &ExecuteMsg::FundInsurance {},
+            &coins(300, NATIVE_DENOM),
+        )
+        .unwrap();
+
+        // the user posts 250 of margin for a long of 5,000 and keeps 350 free
+        let order = |side: Side, price: u128| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side,
+            price: Uint128::new(price),
+            size: Uint128::new(5_000),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        for (addr, side) in [(ADMIN, Side::Sell), (USER, Side::Buy)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &order(side, 1_000_000),
+                &[],
+            )
+            .unwrap();
+        }
+
+        // selling into a bid 10% lower loses 500, only 250 of which is the
+        // user's to lose; the insurance fund covers the rest
+        set_price(&mut app, &oracle_addr, 900_000);
+        for (addr, side) in [(ADMIN, Side::Buy), (USER, Side::Sell)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &order(side, 900_000),
+                &[],
+            )
+            .unwrap();
+        }
+        let res = query_margin_account(&app, &orderbook_contract, USER);
+        assert_eq!(res.free_collateral, Uint128::new(350));
+        let res = query_margin_account(&app, &orderbook_contract, ADMIN);
+        assert_eq!(res.free_collateral, Uint128::new(1100));
+        let res: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(orderbook_contract.addr(), &QueryMsg::InsuranceFund {})
+            .unwrap();
+        assert_eq!(res.balance, Uint128::new(50));
+    }
+
+    #[test]
+    fn treasury_owns_the_insurance_fund() {
+        let (mut app, orderbook_contract, _) = proper_instantiate();
+        app.execute_contract(
+            Addr::unchecked(ADMIN),
+            orderbook_contract.addr(),
+            &ExecuteMsg::FundInsurance {},
+            &coins(300, NATIVE_DENOM),
+        )
+        .unwrap();
+        let res: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(orderbook_contract.addr(), &QueryMsg::InsuranceFund {})
+            .unwrap();
+        assert_eq!(res.balance, Uint128::new(300));
+
+        // only the treasury takes from it, and no more than it holds
+        let withdraw = |amount: u128| ExecuteMsg::WithdrawInsurance {
+            amount: Uint128::new(amount),
+        };
+        let err = app
+            .execute_contract(
+                Addr::unchecked(ADMIN),
+                orderbook_contract.addr(),
+                &withdraw(100),
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::Unauthorized {}
+        ));
+        let err = app
+            .execute_contract(
+                Addr::unchecked(TREASURY),
+                orderbook_contract.addr(),
+                &withdraw(301),
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::InsufficientInsuranceFund { .. }
+        ));
+        app.execute_contract(
+            Addr::unchecked(TREASURY),
+            orderbook_contract.addr(),
+            &withdraw(100),
+            &[],
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(TREASURY, NATIVE_DENOM).unwrap();
+        assert_eq!(balance.amount, Uint128::new(100));
+        let res: InsuranceFundResponse = app
+            .wrap()
+            .query_wasm_smart(orderbook_contract.addr(), &QueryMsg::InsuranceFund {})
+            .unwrap();
+        assert_eq!(res.balance, Uint128::new(200));
+    }
+
+    #[test]
+    fn max_bet_size_covers_resting_orders_and_makers() {
+        let (mut app, orderbook_contract, oracle_addr) = proper_instantiate();
+        let mut msg = create_market_msg("btc");
+        if let ExecuteMsg::CreateMarket { max_bet_size, .. } = &mut msg {
+            *max_bet_size = Uint128::new(1_000);
+        }
+        create_market(&mut app, &orderbook_contract, msg);
+        set_price(&mut app, &oracle_addr, 1_000_000);
+        for (addr, side) in [(USER, Side::Sell), (ADMIN, Side::Buy)] {
+            app.execute_contract(
+                Addr::unchecked(addr),
+                orderbook_contract.addr(),
+                &ExecuteMsg::Deposit {
+                    referral_code: None,
+                },
+                &coins(550, NATIVE_DENOM),
+            )
+            .unwrap();
+            let msg = ExecuteMsg::OpenPosition {
+                market_id: 0,
+                side,
+                size: Uint128::new(1_000),
+                collateral: Uint128::new(50),
+            };
+            app.execute_contract(Addr::unchecked(addr), orderbook_contract.addr(), &msg, &[])
+                .unwrap();
+        }
+        let bid = |size: u128| ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Buy,
+            price: Uint128::new(1_000_000),
+            size: Uint128::new(size),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+
+        // the short of 1,000 leaves room for bids of 2,000, counting the ones
+        // already resting
+        app.execute_contract(
+            Addr::unchecked(USER),
+            orderbook_contract.addr(),
+            &bid(2_000),
+            &[],
+        )
+        .unwrap();
+        let err = app
+            .execute_contract(
+                Addr::unchecked(USER),
+                orderbook_contract.addr(),
+                &bid(1),
+                &[],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::PositionTooLarge { .. }
+        ));
+
+        // once the short is closed, the resting bid only fills up to the max
+        // bet size and the rest of it is cancelled
+        let msg = ExecuteMsg::ClosePosition {
+            market_id: 0,
+            size: None,
+        };
+        app.execute_contract(Addr::unchecked(USER), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let msg = ExecuteMsg::PlaceLimitOrder {
+            market_id: 0,
+            side: Side::Sell,
+            price: Uint128::new(1_000_000),
+            size: Uint128::new(2_000),
+            post_only: false,
+            expires: None,
+            reduce_only: false,
+        };
+        app.execute_contract(Addr::unchecked(ADMIN), orderbook_contract.addr(), &msg, &[])
+            .unwrap();
+        let res: PositionResponse = app
+            .wrap()
+            .query_wasm_smart(
+                orderbook_contract.addr(),
+                &QueryMsg::Position {
+                    owner: USER.to_string(),
+                    market_id: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.position.size, Int128::new(1_000));
+        assert!(query_orders_by_owner(&app, &orderbook_contract, USER)
+            .orders
+            .is_empty());
+        let res = query_orders_by_owner(&app, &orderbook_contract, ADMIN);
+        assert_eq!(res.orders[0].1.remaining(), Uint128::new(1_000));
     }
 }
@@ -1,8 +1,13 @@
+pub mod accounts;
+pub mod book;
 pub mod contract;
 mod error;
+pub mod fees;
 pub mod helpers;
 pub mod integration_tests;
 pub mod msg;
+pub mod position;
 pub mod state;
+pub mod trades;
 
 pub use crate::error::ContractError;
@@ -1,27 +1,601 @@
+use crate::state::{
+    Collateral, FeeTier, Funding, MarginMode, Market, Order, Position, Side, Trade, TriggerKind,
+    TriggerOrder,
+};
+use cosmwasm_std::{Decimal, Int128, Uint128};
+use cw20::{Cw20ReceiveMsg, Expiration};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
-    pub count: i32,
+    pub owner: String,
+    pub oracle: String,
+    pub collateral: Collateral,
+    pub treasury: String,
+    pub trigger_fee: Uint128,
+    pub max_batch_size: u32,
+    pub referral_share_bps: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    Increment {},
-    Reset { count: i32 },
+    CreateMarket {
+        base_asset: String,
+        quote_asset: String,
+        max_bet_size: Uint128,
+        tick_size: Uint128,
+        min_order_size: Uint128,
+        max_order_size: Uint128,
+        max_open_interest: Uint128,
+        max_funding_rate: Decimal,
+        /// Rejects limit orders priced further than this from the mark price.
+        price_band_bps: Option<u64>,
+        /// Refuses orders while the oracle price is older than this.
+        max_price_age: Option<u64>,
+        /// Makes the market a dated contract that settles at this time.
+        expiry: Option<u64>,
+    },
+    UpdateMarket {
+        market_id: u64,
+        max_bet_size: Option<Uint128>,
+        tick_size: Option<Uint128>,
+        min_order_size: Option<Uint128>,
+        max_order_size: Option<Uint128>,
+        max_open_interest: Option<Uint128>,
+        max_funding_rate: Option<Decimal>,
+        /// Zero removes the price band.
+        price_band_bps: Option<u64>,
+        /// Zero removes the limit on the oracle price age.
+        max_price_age: Option<u64>,
+    },
+    CloseMarket {
+        market_id: u64,
+    },
+    /// Stops all trading, funding, liquidations and collateral removal in a
+    /// market, as its expiry does for a dated market.
+    HaltMarket {
+        market_id: u64,
+    },
+    ResumeMarket {
+        market_id: u64,
+    },
+    /// Rests whatever does not match immediately on the book. A post-only
+    /// order is rejected instead if it would match at all. Orders with an
+    /// expiry stop matching once it passes. A reduce-only order is clamped
+    /// to the size of the opposite position and never flips it.
+    PlaceLimitOrder {
+        market_id: u64,
+        side: Side,
+        price: Uint128,
+        size: Uint128,
+        post_only: bool,
+        expires: Option<Expiration>,
+        reduce_only: bool,
+    },
+    /// Fills against the book right away, failing if the average fill price
+    /// is more than `max_slippage_bps` away from the oracle mark price.
+    PlaceMarketOrder {
+        market_id: u64,
+        side: Side,
+        size: Uint128,
+        max_slippage_bps: u64,
+        time_in_force: TimeInForce,
+        reduce_only: bool,
+    },
+    CancelOrder {
+        order_id: u64,
+    },
+    /// Cancels all of the sender's orders, or only those in `market_id`.
+    CancelAll {
+        market_id: Option<u64>,
+    },
+    /// Applies all `cancels`, then all `places` in order, failing as a whole
+    /// if any of them fails. Responds with a `BatchOrdersResponse`.
+    BatchOrders {
+        cancels: Vec<u64>,
+        places: Vec<LimitOrder>,
+    },
+    /// Cancels an order and places one on the same market and side at the new
+    /// price and size, or neither, keeping whether it is reduce-only.
+    /// Responds with the new `PlacedOrder`.
+    ReplaceOrder {
+        order_id: u64,
+        price: Uint128,
+        size: Uint128,
+        post_only: bool,
+        expires: Option<Expiration>,
+    },
+    /// Removes up to `limit` expired orders from a market's book, refunding
+    /// their reserved margin to their owners.
+    PruneExpired {
+        market_id: u64,
+        limit: Option<u32>,
+    },
+    /// Opens or adds to a position at the oracle price, moving `collateral`
+    /// from the margin account to it.
+    OpenPosition {
+        market_id: u64,
+        side: Side,
+        size: Uint128,
+        collateral: Uint128,
+    },
+    /// Closes `size` of the position at the oracle price, or all of it, and
+    /// credits the released collateral plus pnl to the margin account. Under
+    /// cross margin, losses beyond the released collateral come out of free
+    /// collateral, and the insurance fund and ADL cover what it cannot.
+    ClosePosition {
+        market_id: u64,
+        size: Option<Uint128>,
+    },
+    /// Closes the sender's position in an expired market at its settlement
+    /// price, a TWAP of the oracle prices before expiry, crediting the
+    /// collateral plus pnl to the margin account like `ClosePosition`.
+    SettlePosition {
+        market_id: u64,
+    },
+    /// Credits the native collateral sent along to the sender's margin account.
+    /// An account's first deposit may bind it to the owner of
+    /// `referral_code`, who then earns a share of its taker fees.
+    Deposit {
+        referral_code: Option<String>,
+    },
+    /// Withdraws free collateral, as long as the account stays above its
+    /// maintenance margin.
+    Withdraw {
+        amount: Uint128,
+    },
+    Receive(Cw20ReceiveMsg),
+    /// Accrues one funding interval's payment from longs to shorts, or the
+    /// other way around, into the market's cumulative funding index.
+    SettleFunding {
+        market_id: u64,
+    },
+    /// Closes the position of an account below its maintenance margin at the
+    /// oracle price and pays the sender a liquidation bonus.
+    Liquidate {
+        owner: String,
+        market_id: u64,
+    },
+    /// Rests an order off the book until the oracle mark price reaches
+    /// `trigger_price`, reserving the execution fee from the margin account.
+    PlaceTriggerOrder {
+        market_id: u64,
+        side: Side,
+        kind: TriggerKind,
+        trigger_price: Uint128,
+        size: Uint128,
+    },
+    CancelTriggerOrder {
+        trigger_id: u64,
+    },
+    /// Executes up to `limit` triggered orders of a market, paying the sender
+    /// their execution fees. A trigger whose order fails is cancelled and its
+    /// fee refunded to the owner.
+    ExecuteTriggers {
+        market_id: u64,
+        limit: Option<u32>,
+    },
+    /// Executes a single trigger order at `mark_price` on behalf of `keeper`.
+    /// Only the contract itself can call this, from `ExecuteTriggers`.
+    ExecuteTrigger {
+        trigger_id: u64,
+        keeper: String,
+        mark_price: Uint128,
+    },
+    SetMaxBatchSize {
+        max_batch_size: u32,
+    },
+    /// Sets the share of referred traders' taker fees paid to their
+    /// referrers, in basis points.
+    SetReferralShare {
+        referral_share_bps: u64,
+    },
+    /// Registers the sender's referral code, once per account.
+    RegisterReferralCode {
+        code: String,
+    },
+    ClaimReferralRewards {},
+    /// Replaces the fee schedule, ordered by min volume from zero up.
+    UpdateFeeTiers {
+        tiers: Vec<FeeTier>,
+    },
+    /// Forwards the accrued fees to the treasury.
+    SweepFees {},
+    /// Adds the collateral sent along to the treasury's insurance fund.
+    FundInsurance {},
+    /// Sends `amount` of the insurance fund back to the treasury. Only the
+    /// treasury can call it.
+    WithdrawInsurance {
+        amount: Uint128,
+    },
+    /// Switches between cross and isolated margin, only without open
+    /// positions.
+    SetMarginMode {
+        mode: MarginMode,
+    },
+    /// Moves free collateral to an isolated position.
+    AddCollateral {
+        market_id: u64,
+        amount: Uint128,
+    },
+    /// Moves collateral from an isolated position back to the margin account,
+    /// as long as the position stays within the maximum leverage at a fresh
+    /// mark price. Refused while the market is halted or once it expired.
+    RemoveCollateral {
+        market_id: u64,
+        amount: Uint128,
+    },
+    /// Opens the sender's next numbered sub-account, with its own margin,
+    /// positions and orders. Sub-accounts are numbered from one.
+    CreateSubAccount {},
+    /// Lets `delegate` place and cancel orders for one of the sender's
+    /// accounts, zero being the wallet itself. Delegates cannot open or close
+    /// positions, or move collateral.
+    AddDelegate {
+        sub_account: u32,
+        delegate: String,
+    },
+    RemoveDelegate {
+        sub_account: u32,
+        delegate: String,
+    },
+    /// Runs `msg` for a sub-account of `owner`, the sender by default. The
+    /// owner may send any account message, and a delegate only order
+    /// messages. Payouts always go to the owner's wallet.
+    WithAccount {
+        owner: Option<String>,
+        sub_account: u32,
+        msg: Box<ExecuteMsg>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LimitOrder {
+    pub market_id: u64,
+    pub side: Side,
+    pub price: Uint128,
+    pub size: Uint128,
+    pub post_only: bool,
+    pub expires: Option<Expiration>,
+    pub reduce_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    /// Immediate or cancel: fills what it can and drops the rest.
+    Ioc,
+    /// Fill or kill: fails unless the whole size fills.
+    Fok,
+}
+
+/// Messages accepted through a CW20 `Send` of the collateral token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Credits the sender's wallet, or one of its sub-accounts.
+    Deposit {
+        sub_account: Option<u32>,
+        referral_code: Option<String>,
+    },
+    /// Adds to the treasury's insurance fund.
+    FundInsurance {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    // GetCount returns the current count as a json-encoded number
-    GetCount {},
+    Config {},
+    Market {
+        market_id: u64,
+    },
+    ListMarkets {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Aggregated price levels, best first, on each side of a market.
+    OrderBook {
+        market_id: u64,
+        depth: u32,
+    },
+    OrdersByOwner {
+        owner: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    Position {
+        owner: String,
+        market_id: u64,
+    },
+    PositionsByOwner {
+        owner: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    MarginAccount {
+        owner: String,
+    },
+    /// Positions of accounts below their maintenance margin, by owner and
+    /// market id. Each query reads a bounded number of positions, so fewer
+    /// than `limit` may come back before `next_start_after` runs out.
+    LiquidatablePositions {
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    InsuranceFund {},
+    Funding {
+        market_id: u64,
+    },
+    MarketStats {
+        market_id: u64,
+    },
+    TriggerOrdersByOwner {
+        owner: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    FeeTiers {},
+    AccountFees {
+        owner: String,
+    },
+    /// Profitable positions on `side` of a market, in the order they would be
+    /// auto-deleveraged.
+    AdlRanking {
+        market_id: u64,
+        side: Side,
+        limit: Option<u32>,
+    },
+    /// Trades of a market, newest first, optionally within a time range.
+    TradesByMarket {
+        market_id: u64,
+        start_after: Option<u64>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Trades an account made or took, newest first.
+    TradesByAccount {
+        owner: String,
+        start_after: Option<u64>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        limit: Option<u32>,
+    },
+    LastTradePrice {
+        market_id: u64,
+    },
+    ReferralStats {
+        owner: String,
+    },
+    ReferralRewards {
+        owner: String,
+    },
+    /// Sub-accounts of an owner with their trading addresses, which the
+    /// account queries accept as `owner`.
+    SubAccounts {
+        owner: String,
+        start_after: Option<u32>,
+        limit: Option<u32>,
+    },
+    Delegates {
+        owner: String,
+        sub_account: u32,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct GetCountResponse {
-    pub count: i32,
+pub struct ConfigResponse {
+    pub owner: String,
+    pub oracle: String,
+    pub collateral: Collateral,
+    pub treasury: String,
+    pub trigger_fee: Uint128,
+    pub max_batch_size: u32,
+    pub referral_share_bps: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketResponse {
+    pub market_id: u64,
+    pub market: Market,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListMarketsResponse {
+    pub markets: Vec<(u64, Market)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceLevel {
+    pub price: Uint128,
+    pub size: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderBookResponse {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrdersResponse {
+    pub orders: Vec<(u64, Order)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionResponse {
+    pub market_id: u64,
+    pub position: Position,
+    pub mark_price: Uint128,
+    pub unrealized_pnl: Int128,
+    /// Funding accrued since the position was last touched, positive when
+    /// the position owes it.
+    pub pending_funding: Int128,
+    pub leverage: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionsResponse {
+    pub positions: Vec<PositionResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarginAccountResponse {
+    pub mode: MarginMode,
+    pub free_collateral: Uint128,
+    pub used_margin: Uint128,
+    pub unrealized_pnl: Int128,
+    pub equity: Uint128,
+    pub maintenance_margin: Uint128,
+    /// Equity over maintenance margin, unset without open positions. Under
+    /// isolated margin, the lowest ratio of any single position.
+    pub margin_ratio: Option<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatablePosition {
+    pub owner: String,
+    pub market_id: u64,
+    pub size: Int128,
+    pub margin_ratio: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatablePositionsResponse {
+    pub positions: Vec<LiquidatablePosition>,
+    /// Last position read, to continue from as `start_after`; `None` once
+    /// every position has been read.
+    pub next_start_after: Option<(String, u64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InsuranceFundResponse {
+    pub balance: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingResponse {
+    pub market_id: u64,
+    pub funding: Funding,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketStatsResponse {
+    pub market_id: u64,
+    pub long_open_interest: Uint128,
+    pub short_open_interest: Uint128,
+    pub max_open_interest: Uint128,
+    /// Size either side can still grow by before reaching the cap.
+    pub remaining_long_capacity: Uint128,
+    pub remaining_short_capacity: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TriggerOrdersResponse {
+    pub trigger_orders: Vec<(u64, TriggerOrder)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTiersResponse {
+    pub tiers: Vec<FeeTier>,
+    pub accrued_fees: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AccountFeesResponse {
+    /// Notional traded over the rolling volume window.
+    pub volume: Uint128,
+    pub tier: u32,
+    pub maker_fee_bps: i64,
+    pub taker_fee_bps: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdlRank {
+    pub owner: String,
+    pub size: Int128,
+    pub unrealized_pnl: Int128,
+    pub score: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdlRankingResponse {
+    pub market_id: u64,
+    pub mark_price: Uint128,
+    pub positions: Vec<AdlRank>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubAccount {
+    pub sub_account: u32,
+    pub account: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubAccountsResponse {
+    pub sub_accounts: Vec<SubAccount>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DelegatesResponse {
+    pub account: String,
+    pub delegates: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CancelledOrder {
+    pub order_id: u64,
+    /// Reserved margin returned to the margin account.
+    pub refund: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PlacedOrder {
+    pub order_id: u64,
+    pub filled: Uint128,
+    /// Size left resting on the book.
+    pub remaining: Uint128,
+}
+
+/// Data of a `BatchOrders` response, in the order of the request.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchOrdersResponse {
+    pub cancelled: Vec<CancelledOrder>,
+    pub placed: Vec<PlacedOrder>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TradesResponse {
+    pub trades: Vec<(u64, Trade)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LastTradePriceResponse {
+    pub market_id: u64,
+    /// Unset until the market's first trade.
+    pub price: Option<Uint128>,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralStatsResponse {
+    /// The account's own referral code.
+    pub code: Option<String>,
+    /// Who referred the account.
+    pub referrer: Option<String>,
+    pub referred_accounts: u32,
+    /// Taker notional traded by the accounts it referred.
+    pub referred_volume: Uint128,
+    pub total_rewards: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralRewardsResponse {
+    pub unclaimed: Uint128,
+    pub claimed: Uint128,
 }
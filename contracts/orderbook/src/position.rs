@@ -0,0 +1,496 @@
+use cosmwasm_std::{
+    Addr, Decimal, Deps, Empty, Int128, Order, QuerierWrapper, StdError, StdResult, Storage,
+    Uint128,
+};
+use oracle::msg::{GetCurrentPriceResponse, GetOldPricesResponse, QueryMsg as OracleQueryMsg};
+use oracle::state::Price;
+
+use crate::state::{
+    book_prefix, orders, MarginMode, Market, Position, Side, ACCOUNTS, FUNDING, MARKETS,
+    MARKET_POSITIONS, OPEN_INTEREST, POSITIONS, PRICE_PRECISION,
+};
+
+pub const MAX_LEVERAGE: u64 = 20;
+/// Positions scored when ranking a side of a market for auto-deleveraging.
+pub const MAX_ADL_CANDIDATES: usize = 50;
+/// Equity an account must keep, in basis points of its positions' notional.
+pub const MAINTENANCE_MARGIN_BPS: u64 = 500;
+/// Paid to liquidators, in basis points of the liquidated notional.
+pub const LIQUIDATION_BONUS_BPS: u64 = 100;
+/// Share of the closed notional a liquidator receives at least, what the
+/// liquidated account cannot pay coming out of the insurance fund.
+pub const MIN_LIQUIDATION_BONUS_BPS: u64 = 50;
+/// Seconds before expiry over which a dated market's settlement price is
+/// averaged.
+pub const SETTLEMENT_TWAP_WINDOW: u64 = 60 * 60;
+
+/// Reads the current price of a market's pair, and when it was set, from the
+/// oracle contract.
+pub fn query_oracle_price(
+    querier: &QuerierWrapper,
+    oracle: &Addr,
+    market: &Market,
+) -> StdResult<Price> {
+    let res: GetCurrentPriceResponse = querier.query_wasm_smart(
+        oracle,
+        &OracleQueryMsg::GetCurrentPrice {
+            base_asset: market.base_asset.clone(),
+            quote_asset: market.quote_asset.clone(),
+        },
+    )?;
+    Ok(res.price)
+}
+
+/// Reads the current price of a market's pair from the oracle contract.
+pub fn query_mark_price(
+    querier: &QuerierWrapper,
+    oracle: &Addr,
+    market: &Market,
+) -> StdResult<Uint128> {
+    Ok(query_oracle_price(querier, oracle, market)?.amount)
+}
+
+/// Time-weighted average of the oracle's price history for a market's pair
+/// over the `SETTLEMENT_TWAP_WINDOW` seconds up to `end`.
+pub fn query_settlement_price(
+    querier: &QuerierWrapper,
+    oracle: &Addr,
+    market: &Market,
+    end: u64,
+) -> StdResult<Uint128> {
+    let old: GetOldPricesResponse = querier.query_wasm_smart(
+        oracle,
+        &OracleQueryMsg::GetOldPrices {
+            base_asset: market.base_asset.clone(),
+            quote_asset: market.quote_asset.clone(),
+        },
+    )?;
+    let current: GetCurrentPriceResponse = querier.query_wasm_smart(
+        oracle,
+        &OracleQueryMsg::GetCurrentPrice {
+            base_asset: market.base_asset.clone(),
+            quote_asset: market.quote_asset.clone(),
+        },
+    )?;
+    let mut prices: Vec<Price> = old.prices.into_iter().map(|(_, price)| price).collect();
+    prices.push(current.price);
+    prices.sort_by_key(|price| price.timestamp);
+    twap(&prices, end.saturating_sub(SETTLEMENT_TWAP_WINDOW), end)
+        .ok_or_else(|| StdError::generic_err("No oracle price before expiry"))
+}
+
+/// Average of `prices`, sorted by time, over `start..end`, where each price
+/// holds until the next one was set. Falls back to the last price set by
+/// `end` when none held for any of the window.
+pub fn twap(prices: &[Price], start: u64, end: u64) -> Option<Uint128> {
+    let mut weighted = Uint128::zero();
+    let mut weight = 0u64;
+    let mut last = None;
+    for (i, price) in prices.iter().enumerate() {
+        if price.timestamp > end {
+            break;
+        }
+        let from = price.timestamp.max(start);
+        let to = prices
+            .get(i + 1)
+            .map_or(end, |next| next.timestamp.min(end));
+        if to > from {
+            weighted = weighted
+                .checked_add(price.amount.checked_mul((to - from).into()).ok()?)
+                .ok()?;
+            weight += to - from;
+        }
+        last = Some(price.amount);
+    }
+    match weight {
+        0 => last,
+        weight => Some(weighted / Uint128::from(weight)),
+    }
+}
+
+pub fn to_signed(value: Uint128) -> StdResult<Int128> {
+    i128::try_from(value.u128())
+        .map(Int128::new)
+        .map_err(|_| StdError::generic_err(format!("{} does not fit in an Int128", value)))
+}
+
+/// Signed size of a `side` trade, negative when selling.
+pub fn signed_size(side: Side, size: Uint128) -> StdResult<Int128> {
+    let size = to_signed(size)?;
+    Ok(match side {
+        Side::Buy => size,
+        Side::Sell => -size,
+    })
+}
+
+/// Quote value of `size` base units at `price`.
+pub fn notional(size: Uint128, price: Uint128) -> Uint128 {
+    size.multiply_ratio(price, PRICE_PRECISION)
+}
+
+/// Margin needed to open `size` at `price` with the maximum leverage,
+/// rounded up.
+pub fn initial_margin(size: Uint128, price: Uint128) -> Uint128 {
+    let max_leverage = Uint128::from(MAX_LEVERAGE);
+    (notional(size, price) + max_leverage - Uint128::one()) / max_leverage
+}
+
+pub fn maintenance_margin(position: &Position, mark_price: Uint128) -> Uint128 {
+    notional(position.abs_size(), mark_price).multiply_ratio(MAINTENANCE_MARGIN_BPS, 10_000u128)
+}
+
+/// Entry price of a position of `size` at `entry_price` grown by `added` at
+/// `price`, weighted by size.
+pub fn average_entry(
+    size: Uint128,
+    entry_price: Uint128,
+    added: Uint128,
+    price: Uint128,
+) -> StdResult<Uint128> {
+    let value = size
+        .checked_mul(entry_price)?
+        .checked_add(added.checked_mul(price)?)?;
+    Ok(value / (size + added))
+}
+
+/// Profit or loss of a signed `size` moved from `entry_price` to `mark_price`.
+pub fn pnl(size: Int128, entry_price: Uint128, mark_price: Uint128) -> StdResult<Int128> {
+    let price_move = to_signed(mark_price)?.checked_sub(to_signed(entry_price)?)?;
+    Ok(price_move.checked_mul(size)? / Int128::new(PRICE_PRECISION as i128))
+}
+
+pub fn unrealized_pnl(position: &Position, mark_price: Uint128) -> StdResult<Int128> {
+    pnl(position.size, position.entry_price, mark_price)
+}
+
+pub fn funding_index(storage: &dyn Storage, market_id: u64) -> StdResult<Int128> {
+    Ok(FUNDING
+        .may_load(storage, market_id)?
+        .unwrap_or_default()
+        .cumulative_index)
+}
+
+/// Funding a position owes since its last settlement, negative when it is
+/// owed funding instead.
+pub fn pending_funding(position: &Position, funding_index: Int128) -> StdResult<Int128> {
+    let index_move = funding_index.checked_sub(position.funding_index)?;
+    Ok(index_move.checked_mul(position.size)? / Int128::new(PRICE_PRECISION as i128))
+}
+
+/// Settles pending funding into the position's collateral and moves its
+/// snapshot to `funding_index`. Returns the funding paid.
+pub fn settle_funding(position: &mut Position, funding_index: Int128) -> StdResult<Int128> {
+    let owed = pending_funding(position, funding_index)?;
+    position.collateral = settle(position.collateral, -owed)?;
+    position.funding_index = funding_index;
+    Ok(owed)
+}
+
+/// Leverage the position was opened with, its entry notional over collateral.
+pub fn leverage(position: &Position) -> Decimal {
+    if position.collateral.is_zero() {
+        return Decimal::zero();
+    }
+    Decimal::from_ratio(
+        notional(position.abs_size(), position.entry_price),
+        position.collateral,
+    )
+}
+
+/// Collateral plus pnl, floored at zero.
+pub fn settle(collateral: Uint128, pnl: Int128) -> StdResult<Uint128> {
+    let equity = to_signed(collateral)?.checked_add(pnl)?.i128();
+    Ok(Uint128::new(equity.max(0) as u128))
+}
+
+/// Credits a closed part of `owner`'s position to its margin account: the
+/// collateral it releases plus its realized pnl. Under cross margin a loss
+/// beyond the released collateral comes out of the account's free
+/// collateral, while an isolated position loses at most its own collateral.
+/// Returns the loss the account could not cover.
+pub fn realize_pnl(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    released: Uint128,
+    realized_pnl: Int128,
+) -> StdResult<Uint128> {
+    let mut account = ACCOUNTS.may_load(storage, owner)?.unwrap_or_default();
+    let (available, kept) = match account.mode {
+        MarginMode::Cross => (account.collateral + released, Uint128::zero()),
+        MarginMode::Isolated => (released, account.collateral),
+    };
+    let equity = to_signed(available)?.checked_add(realized_pnl)?.i128();
+    account.collateral = kept + Uint128::new(equity.max(0) as u128);
+    ACCOUNTS.save(storage, owner, &account)?;
+    Ok(Uint128::new(equity.min(0).unsigned_abs()))
+}
+
+/// Applies a fill of `size` at `price` to the owner's position in a market.
+/// `margin` is the initial margin that was reserved for the filled size. It
+/// is posted to the position when the fill adds exposure, and returned to
+/// the margin account, along with the realized pnl, when it reduces it.
+///
+/// A fill larger than an opposite position flips it in one step. The whole
+/// old position closes at the fill price: its pnl against the old entry
+/// price is realized and its collateral is released along with the margin
+/// for the closed size. The rest of the fill opens a new position on the
+/// fill's side with the fill price as its entry price, the remaining share
+/// of `margin` as collateral and a fresh funding snapshot. The realized pnl
+/// is settled like a close through `realize_pnl`, so an isolated position
+/// loses at most its own collateral. Returns the size closed and the loss
+/// that could not be covered.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_fill(
+    storage: &mut dyn Storage,
+    now: u64,
+    owner: &Addr,
+    market_id: u64,
+    side: Side,
+    size: Uint128,
+    price: Uint128,
+    margin: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let key = (owner, market_id);
+    let funding_index = funding_index(storage, market_id)?;
+    let mut position = match POSITIONS.may_load(storage, key)? {
+        None => Position {
+            size: Int128::zero(),
+            entry_price: price,
+            collateral: Uint128::zero(),
+            opened_at: now,
+            funding_index,
+        },
+        Some(mut position) => {
+            settle_funding(&mut position, funding_index)?;
+            position
+        }
+    };
+    let old_size = position.size;
+
+    if position.size.is_zero() || position.side() == side {
+        position.entry_price =
+            average_entry(position.abs_size(), position.entry_price, size, price)?;
+        position.size += signed_size(side, size)?;
+        position.collateral += margin;
+        save_position(storage, owner, market_id, old_size, &position)?;
+        return Ok((Uint128::zero(), Uint128::zero()));
+    }
+
+    let open_size = position.abs_size();
+    let closed = size.min(open_size);
+    let closed_signed = signed_size(position.side(), closed)?;
+    let realized_pnl = pnl(closed_signed, position.entry_price, price)?;
+    let released = position.collateral.multiply_ratio(closed, open_size);
+    // whatever is left of the fill flips the position at the fill price
+    let flipped = size - closed;
+    let flip_margin = margin.multiply_ratio(flipped, size);
+
+    let mut account = ACCOUNTS.may_load(storage, owner)?.unwrap_or_default();
+    account.collateral += margin - flip_margin;
+    ACCOUNTS.save(storage, owner, &account)?;
+    let shortfall = realize_pnl(storage, owner, released, realized_pnl)?;
+
+    if !flipped.is_zero() {
+        position = Position {
+            size: signed_size(side, flipped)?,
+            entry_price: price,
+            collateral: flip_margin,
+            opened_at: now,
+            funding_index,
+        };
+    } else {
+        position.size -= closed_signed;
+        position.collateral -= released;
+    }
+    save_position(storage, owner, market_id, old_size, &position)?;
+    Ok((closed, shortfall))
+}
+
+/// Stores a position, removing it once its size is zero, and moves the
+/// market's open interest and its index entry from `old_size` to its new
+/// size.
+pub fn save_position(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    market_id: u64,
+    old_size: Int128,
+    position: &Position,
+) -> StdResult<()> {
+    let index_key = |position: &Position| {
+        let entry_price = match position.side() {
+            Side::Buy => position.entry_price.u128(),
+            Side::Sell => u128::MAX - position.entry_price.u128(),
+        };
+        (book_prefix(market_id, position.side()), entry_price, owner)
+    };
+    // the index follows the stored position, whose entry price may differ
+    if let Some(stored) = POSITIONS.may_load(storage, (owner, market_id))? {
+        MARKET_POSITIONS.remove(storage, index_key(&stored));
+    }
+    if position.size.is_zero() {
+        POSITIONS.remove(storage, (owner, market_id));
+    } else {
+        POSITIONS.save(storage, (owner, market_id), position)?;
+        MARKET_POSITIONS.save(storage, index_key(position), &Empty {})?;
+    }
+
+    let long = |size: Int128| Uint128::new(size.i128().max(0) as u128);
+    let short = |size: Int128| Uint128::new(size.i128().min(0).unsigned_abs());
+    let mut open_interest = OPEN_INTEREST
+        .may_load(storage, market_id)?
+        .unwrap_or_default();
+    open_interest.long = open_interest.long + long(position.size) - long(old_size);
+    open_interest.short = open_interest.short + short(position.size) - short(old_size);
+    OPEN_INTEREST.save(storage, market_id, &open_interest)
+}
+
+/// Margin state of an account across all of its positions and orders.
+pub struct MarginSummary {
+    /// Free collateral in the margin account.
+    pub collateral: Uint128,
+    /// Collateral posted to positions and reserved by resting orders.
+    pub used_margin: Uint128,
+    pub unrealized_pnl: Int128,
+    pub maintenance_margin: Uint128,
+}
+
+impl MarginSummary {
+    pub fn equity(&self) -> StdResult<Uint128> {
+        settle(self.collateral + self.used_margin, self.unrealized_pnl)
+    }
+
+    /// Equity over maintenance margin, `None` without open positions. An
+    /// account below 1 is undercollateralized.
+    pub fn margin_ratio(&self) -> StdResult<Option<Decimal>> {
+        if self.maintenance_margin.is_zero() {
+            return Ok(None);
+        }
+        Ok(Some(Decimal::from_ratio(
+            self.equity()?,
+            self.maintenance_margin,
+        )))
+    }
+}
+
+/// Summary of a single position standing on its own collateral.
+pub fn position_summary(
+    deps: Deps,
+    oracle: &Addr,
+    market_id: u64,
+    position: &Position,
+) -> StdResult<MarginSummary> {
+    let market = MARKETS.load(deps.storage, market_id)?;
+    let mark_price = query_mark_price(&deps.querier, oracle, &market)?;
+    let funding = pending_funding(position, funding_index(deps.storage, market_id)?)?;
+    Ok(MarginSummary {
+        collateral: Uint128::zero(),
+        used_margin: position.collateral,
+        unrealized_pnl: unrealized_pnl(position, mark_price)?.checked_sub(funding)?,
+        maintenance_margin: maintenance_margin(position, mark_price),
+    })
+}
+
+pub fn margin_summary(deps: Deps, oracle: &Addr, owner: &Addr) -> StdResult<MarginSummary> {
+    let mut summary = MarginSummary {
+        collateral: ACCOUNTS
+            .may_load(deps.storage, owner)?
+            .unwrap_or_default()
+            .collateral,
+        used_margin: Uint128::zero(),
+        unrealized_pnl: Int128::zero(),
+        maintenance_margin: Uint128::zero(),
+    };
+    for item in POSITIONS
+        .prefix(owner)
+        .range(deps.storage, None, None, Order::Ascending)
+    {
+        let (market_id, position) = item?;
+        let position = position_summary(deps, oracle, market_id, &position)?;
+        summary.used_margin += position.used_margin;
+        summary.unrealized_pnl += position.unrealized_pnl;
+        summary.maintenance_margin += position.maintenance_margin;
+    }
+    for item in
+        orders()
+            .idx
+            .owner
+            .prefix(owner.clone())
+            .range(deps.storage, None, None, Order::Ascending)
+    {
+        let (_, order) = item?;
+        summary.used_margin += order.margin;
+    }
+    Ok(summary)
+}
+
+/// Summary that decides whether the owner's position in `market_id` can be
+/// liquidated: the whole account's under cross margin, and the position's
+/// own under isolated margin.
+pub fn liquidation_summary(
+    deps: Deps,
+    oracle: &Addr,
+    owner: &Addr,
+    market_id: u64,
+    position: &Position,
+) -> StdResult<MarginSummary> {
+    match margin_mode(deps.storage, owner)? {
+        MarginMode::Cross => margin_summary(deps, oracle, owner),
+        MarginMode::Isolated => position_summary(deps, oracle, market_id, position),
+    }
+}
+
+pub fn margin_mode(storage: &dyn Storage, owner: &Addr) -> StdResult<MarginMode> {
+    Ok(ACCOUNTS.may_load(storage, owner)?.unwrap_or_default().mode)
+}
+
+/// A profitable position that auto-deleveraging would reduce.
+pub struct AdlCandidate {
+    pub owner: Addr,
+    pub position: Position,
+    /// Unrealized pnl net of pending funding.
+    pub unrealized_pnl: Int128,
+    /// Pnl over collateral times leverage at the mark price; the highest
+    /// scores are deleveraged first.
+    pub score: Decimal,
+}
+
+/// Profitable positions on `side` of a market, in the order auto-deleveraging
+/// reduces them: highest score first, then by owner. Only the
+/// `MAX_ADL_CANDIDATES` positions with the best entry prices are scored, which
+/// bounds the work however many positions the market has.
+pub fn adl_ranking(
+    storage: &dyn Storage,
+    market_id: u64,
+    side: Side,
+    mark_price: Uint128,
+) -> StdResult<Vec<AdlCandidate>> {
+    let funding_index = funding_index(storage, market_id)?;
+    let mut candidates = vec![];
+    for key in MARKET_POSITIONS
+        .sub_prefix(book_prefix(market_id, side))
+        .keys(storage, None, None, Order::Ascending)
+        .take(MAX_ADL_CANDIDATES)
+    {
+        let (_, owner) = key?;
+        let position = POSITIONS.load(storage, (&owner, market_id))?;
+        let unrealized_pnl = unrealized_pnl(&position, mark_price)?
+            .checked_sub(pending_funding(&position, funding_index)?)?;
+        if unrealized_pnl <= Int128::zero() {
+            continue;
+        }
+        let collateral = position.collateral.max(Uint128::one());
+        let profit = Decimal::checked_from_ratio(unrealized_pnl.i128() as u128, collateral)
+            .unwrap_or(Decimal::MAX);
+        let leverage =
+            Decimal::checked_from_ratio(notional(position.abs_size(), mark_price), collateral)
+                .unwrap_or(Decimal::MAX);
+        candidates.push(AdlCandidate {
+            owner,
+            position,
+            unrealized_pnl,
+            score: profit.checked_mul(leverage).unwrap_or(Decimal::MAX),
+        });
+    }
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.owner.cmp(&b.owner)));
+    Ok(candidates)
+}
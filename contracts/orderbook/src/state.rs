@@ -1,23 +1,395 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Decimal, Empty, Int128, Uint128};
+use cw20::Expiration;
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct State {
-    pub count: i32,
+pub struct Config {
     pub owner: Addr,
+    pub oracle: Addr,
+    pub collateral: Collateral,
+    /// Receives the trading fees the contract accrues.
+    pub treasury: Addr,
+    /// Reserved from the margin account for each trigger order and paid to
+    /// the keeper that executes it.
+    pub trigger_fee: Uint128,
+    /// Most cancels and placements a single `BatchOrders` may carry.
+    pub max_batch_size: u32,
+    /// Share of a referred trader's taker fees paid to its referrer.
+    pub referral_share_bps: u64,
 }
 
+/// The single asset margin is deposited, held and paid out in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Collateral {
+    Native { denom: String },
+    Cw20 { address: String },
+}
+
+/// Scale of order and oracle prices: a price of `PRICE_PRECISION` is one
+/// quote unit per base unit.
+pub const PRICE_PRECISION: u128 = 1_000_000;
+
+/// Fees charged to accounts whose rolling volume reaches `min_volume`. A
+/// negative maker fee is a rebate paid out of the taker's fee.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct FeeTier {
+    pub min_volume: Uint128,
+    pub maker_fee_bps: i64,
+    pub taker_fee_bps: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct Referrals {
+    pub referred_accounts: u32,
+    pub referred_volume: Uint128,
+    pub total_rewards: Uint128,
+    pub unclaimed_rewards: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketStatus {
+    Active,
+    /// Stopped by its owner in an emergency, until resumed.
+    Halted,
+    Closed,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Market {
+    //oracle pair the market is marked against
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// Largest position, in base units, a single account may hold.
     pub max_bet_size: Uint128,
+    /// Order prices must be a multiple of this.
+    pub tick_size: Uint128,
+    pub min_order_size: Uint128,
+    pub max_order_size: Uint128,
+    /// Cap on the total size of either all longs or all shorts.
+    pub max_open_interest: Uint128,
+    /// Bound on a funding interval's payment, as a fraction of the index price.
+    pub max_funding_rate: Decimal,
+    /// Furthest a limit price may be from the mark price, in basis points.
+    pub price_band_bps: Option<u64>,
+    /// Oldest oracle price, in seconds, that orders may be placed against.
+    pub max_price_age: Option<u64>,
+    /// When a dated market stops trading and its positions settle.
+    pub expiry: Option<u64>,
+    /// Set from the oracle price history once an expired market settles.
+    pub settlement_price: Option<Uint128>,
+    pub owner: Addr,
+    pub status: MarketStatus,
+}
+
+impl Market {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry.is_some_and(|expiry| now >= expiry)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct OpenInterest {
+    pub long: Uint128,
+    pub short: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct Funding {
+    /// Sum of all funding payments per base unit, in price units. Longs pay
+    /// when it rises and shorts pay when it falls.
+    pub cumulative_index: Int128,
+    pub last_payment: Int128,
+    pub last_settled: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Order {
+    pub market_id: u64,
     pub owner: Addr,
+    pub side: Side,
+    pub price: Uint128,
+    pub size: Uint128,
+    pub filled: Uint128,
+    pub created_at: u64,
+    /// Initial margin still reserved for the unfilled size.
+    pub margin: Uint128,
+    /// When the order stops matching and can be pruned from the book.
+    #[serde(default)]
+    pub expires: Expiration,
+    /// Only ever reduces the owner's position. Whenever the order matches,
+    /// its remaining size is clamped to what is open on the other side of
+    /// the position, and the order is cancelled once nothing is.
+    #[serde(default)]
+    pub reduce_only: bool,
+}
+
+impl Order {
+    pub fn remaining(&self) -> Uint128 {
+        self.size - self.filled
+    }
+}
+
+/// Key that sorts a market's resting orders best price first, then oldest
+/// first, when ranged in ascending order. Bids store the inverted price so
+/// that the highest bid comes first.
+pub fn book_key(order: &Order) -> (u64, u8, u128) {
+    match order.side {
+        Side::Buy => (order.market_id, 0, u128::MAX - order.price.u128()),
+        Side::Sell => (order.market_id, 1, order.price.u128()),
+    }
+}
+
+pub fn book_prefix(market_id: u64, side: Side) -> (u64, u8) {
+    match side {
+        Side::Buy => (market_id, 0),
+        Side::Sell => (market_id, 1),
+    }
+}
+
+/// Orders a market's orders by when they expire, those expiring at a height
+/// before those expiring at a time and orders that never expire last.
+pub fn expiry_key(order: &Order) -> (u64, u8, u64) {
+    match order.expires {
+        Expiration::AtHeight(height) => (order.market_id, 0, height),
+        Expiration::AtTime(time) => (order.market_id, 1, time.nanos()),
+        Expiration::Never {} => (order.market_id, 2, 0),
+    }
+}
+
+pub struct OrderIndexes<'a> {
+    pub book: MultiIndex<'a, (u64, u8, u128), Order, u64>,
+    pub owner: MultiIndex<'a, Addr, Order, u64>,
+    pub expiry: MultiIndex<'a, (u64, u8, u64), Order, u64>,
+}
+
+impl<'a> IndexList<Order> for OrderIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Order>> + '_> {
+        let v: Vec<&dyn Index<Order>> = vec![&self.book, &self.owner, &self.expiry];
+        Box::new(v.into_iter())
+    }
 }
 
+pub fn orders<'a>() -> IndexedMap<'a, u64, Order, OrderIndexes<'a>> {
+    let indexes = OrderIndexes {
+        book: MultiIndex::new(book_key, "orders", "orders__book"),
+        owner: MultiIndex::new(|order| order.owner.clone(), "orders", "orders__owner"),
+        expiry: MultiIndex::new(expiry_key, "orders", "orders__expiry"),
+    };
+    IndexedMap::new("orders", indexes)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+    /// Trades at market once the mark price moves against the order's side:
+    /// a buy at or above the trigger price, a sell at or below it.
+    StopLoss,
+    /// Trades at market once the mark price moves in favour of the order's
+    /// side: a buy at or below the trigger price, a sell at or above it.
+    TakeProfit,
+    /// Triggers like a stop loss, but places a limit order at `limit_price`.
+    StopLimit { limit_price: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TriggerOrder {
+    pub market_id: u64,
+    pub owner: Addr,
+    pub side: Side,
+    pub kind: TriggerKind,
+    pub trigger_price: Uint128,
+    pub size: Uint128,
+    pub created_at: u64,
+    /// Execution fee reserved from the owner's margin account.
+    pub fee: Uint128,
+}
+
+impl TriggerOrder {
+    pub fn triggered(&self, mark_price: Uint128) -> bool {
+        match (&self.kind, self.side) {
+            (TriggerKind::TakeProfit, Side::Buy) => mark_price <= self.trigger_price,
+            (TriggerKind::TakeProfit, Side::Sell) => mark_price >= self.trigger_price,
+            (_, Side::Buy) => mark_price >= self.trigger_price,
+            (_, Side::Sell) => mark_price <= self.trigger_price,
+        }
+    }
+}
+
+/// Orders a market's trigger orders by the way the mark price has to move to
+/// trigger them, 0 for at or above their trigger price and 1 for at or below
+/// it, then by trigger price.
+pub fn trigger_key(order: &TriggerOrder) -> (u64, u8, u128) {
+    let direction = match (&order.kind, order.side) {
+        (TriggerKind::TakeProfit, Side::Sell) => 0,
+        (TriggerKind::TakeProfit, Side::Buy) => 1,
+        (_, Side::Buy) => 0,
+        (_, Side::Sell) => 1,
+    };
+    (order.market_id, direction, order.trigger_price.u128())
+}
+
+pub struct TriggerOrderIndexes<'a> {
+    pub trigger: MultiIndex<'a, (u64, u8, u128), TriggerOrder, u64>,
+    pub owner: MultiIndex<'a, Addr, TriggerOrder, u64>,
+}
+
+impl<'a> IndexList<TriggerOrder> for TriggerOrderIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<TriggerOrder>> + '_> {
+        let v: Vec<&dyn Index<TriggerOrder>> = vec![&self.trigger, &self.owner];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn trigger_orders<'a>() -> IndexedMap<'a, u64, TriggerOrder, TriggerOrderIndexes<'a>> {
+    let indexes = TriggerOrderIndexes {
+        trigger: MultiIndex::new(trigger_key, "trigger_orders", "trigger_orders__trigger"),
+        owner: MultiIndex::new(
+            |order| order.owner.clone(),
+            "trigger_orders",
+            "trigger_orders__owner",
+        ),
+    };
+    IndexedMap::new("trigger_orders", indexes)
+}
+
+/// A match between a resting maker order and an incoming taker order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Trade {
+    pub market_id: u64,
+    pub maker: Addr,
+    pub taker: Addr,
+    pub maker_order_id: u64,
+    pub taker_order_id: u64,
+    /// Side the taker traded on.
+    pub side: Side,
+    pub price: Uint128,
+    pub size: Uint128,
+    /// Negative when the maker earned a rebate.
+    pub maker_fee: Int128,
+    pub taker_fee: Uint128,
+    pub height: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Position {
+    /// Base units held, positive for a long and negative for a short.
+    pub size: Int128,
+    pub entry_price: Uint128,
+    pub collateral: Uint128,
+    pub opened_at: u64,
+    /// Cumulative funding index of the market when funding was last settled.
+    pub funding_index: Int128,
+}
+
+impl Position {
+    pub fn side(&self) -> Side {
+        if self.size.i128() < 0 {
+            Side::Sell
+        } else {
+            Side::Buy
+        }
+    }
+
+    pub fn abs_size(&self) -> Uint128 {
+        Uint128::new(self.size.i128().unsigned_abs())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MarginMode {
+    /// Free collateral and all positions back each other, and the account is
+    /// liquidated as a whole.
+    #[default]
+    Cross,
+    /// Each position stands on its own collateral. Free collateral is never
+    /// used to cover a position's losses.
+    Isolated,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, JsonSchema)]
+pub struct MarginAccount {
+    /// Deposited collateral not posted to any position or order.
+    pub collateral: Uint128,
+    #[serde(default)]
+    pub mode: MarginMode,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+//ordered by min volume, starting at zero
+pub const FEE_TIERS: Item<Vec<FeeTier>> = Item::new("fee_tiers");
+//fees collected net of rebates, not yet swept to the treasury
+pub const ACCRUED_FEES: Item<Uint128> = Item::new("accrued_fees");
+//key is owner, day since the unix epoch; value is traded notional
+pub const VOLUMES: Map<(&Addr, u64), Uint128> = Map::new("volumes");
+//key is referral code; value is its owner
+pub const REFERRAL_CODES: Map<&str, Addr> = Map::new("referral_codes");
+//key is referrer; value is its referral code
+pub const ACCOUNT_REFERRAL_CODES: Map<&Addr, String> = Map::new("account_referral_codes");
+//key is referred account; value is its referrer
+pub const REFERRERS: Map<&Addr, Addr> = Map::new("referrers");
+//key is referrer
+pub const REFERRALS: Map<&Addr, Referrals> = Map::new("referrals");
+
+//key is market id, assigned sequentially
+pub const MARKETS: Map<u64, Market> = Map::new("markets");
+pub const MARKET_COUNT: Item<u64> = Item::new("market_count");
+//key is market id
+pub const FUNDING: Map<u64, Funding> = Map::new("funding");
+//key is market id
+pub const OPEN_INTEREST: Map<u64, OpenInterest> = Map::new("open_interest");
+
+//resting orders are stored in orders(), keyed by order id
+pub const ORDER_COUNT: Item<u64> = Item::new("order_count");
+//pending trigger orders are stored in trigger_orders(), keyed by trigger id
+pub const TRIGGER_COUNT: Item<u64> = Item::new("trigger_count");
+
+//key is trade id, assigned sequentially
+pub const TRADES: Map<u64, Trade> = Map::new("trades");
+pub const TRADE_COUNT: Item<u64> = Item::new("trade_count");
+//key is market id, timestamp, trade id
+pub const MARKET_TRADES: Map<(u64, u64, u64), Empty> = Map::new("market_trades");
+//key is maker or taker, timestamp, trade id
+pub const ACCOUNT_TRADES: Map<(&Addr, u64, u64), Empty> = Map::new("account_trades");
 
-pub const STATE: Item<State> = Item::new("state");
+pub const ACCOUNTS: Map<&Addr, MarginAccount> = Map::new("accounts");
+//key is owner, sub-account number; value is the sub-account's trading address
+pub const SUB_ACCOUNTS: Map<(&Addr, u32), Addr> = Map::new("sub_accounts");
+//key is owner; value is the number of sub-accounts created, not counting the wallet
+pub const SUB_ACCOUNT_COUNT: Map<&Addr, u32> = Map::new("sub_account_count");
+//key is sub-account trading address; value is its owner
+pub const ACCOUNT_OWNERS: Map<&Addr, Addr> = Map::new("account_owners");
+//key is trading account, delegate allowed to place and cancel its orders
+pub const DELEGATES: Map<(&Addr, &Addr), Empty> = Map::new("delegates");
+//the treasury's stake backing liquidations: it receives what liquidations
+//leave over, covers their shortfalls, and only the treasury can withdraw it
+pub const INSURANCE_FUND: Item<Uint128> = Item::new("insurance_fund");
 
-pub const MARKETS: Map
+//key is owner, market id
+pub const POSITIONS: Map<(&Addr, u64), Position> = Map::new("positions");
+//key is (market id, side) as in `book_prefix`, entry price, owner; indexes
+//the open positions on each side of a market, longs by increasing and shorts
+//by decreasing entry price, so the most profitable per unit come first
+pub const MARKET_POSITIONS: Map<((u64, u8), u128, &Addr), Empty> = Map::new("market_positions");
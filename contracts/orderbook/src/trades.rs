@@ -0,0 +1,73 @@
+use cosmwasm_std::{Empty, Event, Order, StdResult, Storage};
+use cw_storage_plus::{Bound, Prefix};
+
+use crate::state::{Side, Trade, ACCOUNT_TRADES, MARKET_TRADES, TRADES, TRADE_COUNT};
+
+/// Stores a trade under its market and both of its accounts, returning its id.
+pub fn record_trade(storage: &mut dyn Storage, trade: &Trade) -> StdResult<u64> {
+    let trade_id = TRADE_COUNT.may_load(storage)?.unwrap_or_default();
+    TRADE_COUNT.save(storage, &(trade_id + 1))?;
+    TRADES.save(storage, trade_id, trade)?;
+    MARKET_TRADES.save(
+        storage,
+        (trade.market_id, trade.timestamp, trade_id),
+        &Empty {},
+    )?;
+    for account in [&trade.maker, &trade.taker] {
+        ACCOUNT_TRADES.save(storage, (account, trade.timestamp, trade_id), &Empty {})?;
+    }
+    Ok(trade_id)
+}
+
+/// Event indexers pick up for each match.
+pub fn fill_event(trade_id: u64, trade: &Trade) -> Event {
+    let side = match trade.side {
+        Side::Buy => "buy",
+        Side::Sell => "sell",
+    };
+    Event::new("fill")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("market_id", trade.market_id.to_string())
+        .add_attribute("maker", trade.maker.clone())
+        .add_attribute("taker", trade.taker.clone())
+        .add_attribute("maker_order_id", trade.maker_order_id.to_string())
+        .add_attribute("taker_order_id", trade.taker_order_id.to_string())
+        .add_attribute("side", side)
+        .add_attribute("price", trade.price)
+        .add_attribute("size", trade.size)
+        .add_attribute("maker_fee", trade.maker_fee.to_string())
+        .add_attribute("taker_fee", trade.taker_fee)
+}
+
+/// Up to `limit` trades from a market or account index, newest first. Pages
+/// continue before `start_after` and stay within `start_time..=end_time`.
+pub fn trades_page(
+    storage: &dyn Storage,
+    index: Prefix<(u64, u64), Empty, (u64, u64)>,
+    start_after: Option<u64>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<(u64, Trade)>> {
+    let min = start_time.map(|time| Bound::inclusive((time, 0)));
+    let after = match start_after {
+        Some(trade_id) => Some((TRADES.load(storage, trade_id)?.timestamp, trade_id)),
+        None => None,
+    };
+    let max = match (after, end_time) {
+        (Some(after), Some(end_time)) if end_time < after.0 => {
+            Some(Bound::inclusive((end_time, u64::MAX)))
+        }
+        (Some(after), _) => Some(Bound::exclusive(after)),
+        (None, Some(end_time)) => Some(Bound::inclusive((end_time, u64::MAX))),
+        (None, None) => None,
+    };
+    index
+        .keys(storage, min, max, Order::Descending)
+        .take(limit)
+        .map(|key| {
+            let (_, trade_id) = key?;
+            Ok((trade_id, TRADES.load(storage, trade_id)?))
+        })
+        .collect()
+}
@@ -3,8 +3,11 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use token::msg::{ExecuteMsg, GetCountResponse, InstantiateMsg, QueryMsg};
-use token::state::State;
+use token::msg::{
+    ConfigResponse, ExecuteMsg, FeeExemptResponse, FrozenResponse, InstantiateMsg,
+    MintHistoryResponse, NonceResponse, PermitPayload, QueryMsg,
+};
+use token::state::{Config, TokenInfo};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -15,6 +18,12 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(State), &out_dir);
-    export_schema(&schema_for!(GetCountResponse), &out_dir);
+    export_schema(&schema_for!(Config), &out_dir);
+    export_schema(&schema_for!(TokenInfo), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schema(&schema_for!(FrozenResponse), &out_dir);
+    export_schema(&schema_for!(FeeExemptResponse), &out_dir);
+    export_schema(&schema_for!(NonceResponse), &out_dir);
+    export_schema(&schema_for!(PermitPayload), &out_dir);
+    export_schema(&schema_for!(MintHistoryResponse), &out_dir);
 }
@@ -1,11 +1,31 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{
+    coins, to_binary, to_vec, Addr, BankMsg, Binary, CanonicalAddr, Deps, DepsMut, Env,
+    MessageInfo, Order, Response, StdResult, Storage, Uint128,
+};
 use cw2::set_contract_version;
+use cw20::{
+    AllowanceResponse, BalanceResponse, Cw20ReceiveMsg, Expiration, MinterResponse,
+    TokenInfoResponse,
+};
+use cw_storage_plus::Bound;
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, GetCountResponse, InstantiateMsg, QueryMsg};
-use crate::state::{State, STATE};
+use crate::msg::{
+    ConfigResponse, ExecuteMsg, FeeExemptResponse, FrozenResponse, InstantiateMsg,
+    MintHistoryResponse, NonceResponse, PermitPayload, QueryMsg,
+};
+use crate::state::{
+    Config, MinterData, SupplyAction, SupplyChange, TokenInfo, ALLOWANCES, BALANCES, CONFIG,
+    FEE_EXEMPT, FROZEN, NONCES, SUPPLY_LOG, SUPPLY_LOG_COUNT, TOKEN_INFO,
+};
+
+const MAX_FEE_BPS: u16 = 10_000;
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:token";
@@ -14,135 +34,1522 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-    let state = State {
-        count: msg.count,
+    // a wrapper starts empty so that supply always matches the locked coins,
+    // and has no minter whose cap would limit deposits
+    if msg.wrapped_denom.is_some()
+        && (!msg.initial_balances.is_empty() || !info.funds.is_empty() || msg.mint.is_some())
+    {
+        return Err(ContractError::WrapperMode {});
+    }
+
+    let minter = match msg.mint {
+        Some(mint) => Some(MinterData {
+            minter: deps.api.addr_validate(&mint.minter)?,
+            cap: mint.cap,
+        }),
+        None => None,
+    };
+    let config = Config {
         owner: info.sender.clone(),
+        minter,
+        pauser: optional_addr_validate(deps.as_ref(), msg.pauser)?,
+        freezer: optional_addr_validate(deps.as_ref(), msg.freezer)?,
+        paused: false,
+        wrapped_denom: msg.wrapped_denom,
+        fee_bps: 0,
+        fee_recipient: None,
+    };
+    let token_info = TokenInfo {
+        name: msg.name,
+        symbol: msg.symbol,
+        decimals: msg.decimals,
+        total_supply: Uint128::zero(),
     };
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    STATE.save(deps.storage, &state)?;
+    CONFIG.save(deps.storage, &config)?;
+    TOKEN_INFO.save(deps.storage, &token_info)?;
+
+    // initial balances go through the audit log like any other mint
+    let mut total_supply = Uint128::zero();
+    for row in msg.initial_balances {
+        let address = deps.api.addr_validate(&row.address)?;
+        mint_balance(deps.storage, &env, &info.sender, &address, row.amount)?;
+        total_supply = total_supply.checked_add(row.amount)?;
+    }
 
     Ok(Response::new()
         .add_attribute("method", "instantiate")
         .add_attribute("owner", info.sender)
-        .add_attribute("count", msg.count.to_string()))
+        .add_attribute("total_supply", total_supply))
+}
+
+fn optional_addr_validate(deps: Deps, addr: Option<String>) -> StdResult<Option<Addr>> {
+    addr.map(|addr| deps.api.addr_validate(&addr)).transpose()
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    // coins sent along with anything but a deposit would be locked without
+    // tokens minted for them
+    if !info.funds.is_empty()
+        && !matches!(msg, ExecuteMsg::Deposit {})
+        && CONFIG.load(deps.storage)?.wrapped_denom.is_some()
+    {
+        return Err(ContractError::FundsNotAccepted {});
+    }
     match msg {
-        ExecuteMsg::Increment {} => try_increment(deps),
-        ExecuteMsg::Reset { count } => try_reset(deps, info, count),
+        ExecuteMsg::Transfer { recipient, amount } => try_transfer(deps, info, recipient, amount),
+        ExecuteMsg::Burn { amount } => try_burn(deps, env, info, amount),
+        ExecuteMsg::Send {
+            contract,
+            amount,
+            msg,
+        } => try_send(deps, info, contract, amount, msg),
+        ExecuteMsg::Mint { recipient, amount } => try_mint(deps, env, info, recipient, amount),
+        ExecuteMsg::UpdateMinter { new_minter } => try_update_minter(deps, info, new_minter),
+        ExecuteMsg::IncreaseAllowance {
+            spender,
+            amount,
+            expires,
+        } => try_increase_allowance(deps, env, info, spender, amount, expires),
+        ExecuteMsg::DecreaseAllowance {
+            spender,
+            amount,
+            expires,
+        } => try_decrease_allowance(deps, env, info, spender, amount, expires),
+        ExecuteMsg::TransferFrom {
+            owner,
+            recipient,
+            amount,
+        } => try_transfer_from(deps, env, info, owner, recipient, amount),
+        ExecuteMsg::SendFrom {
+            owner,
+            contract,
+            amount,
+            msg,
+        } => try_send_from(deps, env, info, owner, contract, amount, msg),
+        ExecuteMsg::BurnFrom { owner, amount } => try_burn_from(deps, env, info, owner, amount),
+        ExecuteMsg::UpdateConfig {
+            owner,
+            pauser,
+            freezer,
+        } => try_update_config(deps, info, owner, pauser, freezer),
+        ExecuteMsg::Pause {} => try_set_paused(deps, info, true),
+        ExecuteMsg::Unpause {} => try_set_paused(deps, info, false),
+        ExecuteMsg::Freeze { address } => try_set_frozen(deps, info, address, true),
+        ExecuteMsg::Unfreeze { address } => try_set_frozen(deps, info, address, false),
+        ExecuteMsg::Deposit {} => try_deposit(deps, env, info),
+        ExecuteMsg::Withdraw { amount } => try_withdraw(deps, env, info, amount),
+        ExecuteMsg::UpdateTransferFee {
+            fee_bps,
+            fee_recipient,
+        } => try_update_transfer_fee(deps, info, fee_bps, fee_recipient),
+        ExecuteMsg::SetFeeExempt { address, exempt } => {
+            try_set_fee_exempt(deps, info, address, exempt)
+        }
+        ExecuteMsg::Permit {
+            owner_pubkey,
+            spender,
+            amount,
+            expiry,
+            nonce,
+            signature,
+        } => try_permit(
+            deps,
+            env,
+            owner_pubkey,
+            spender,
+            amount,
+            expiry,
+            nonce,
+            signature,
+        ),
+    }
+}
+
+/// Fails if the token is paused or any of the given accounts is frozen.
+fn assert_can_move(storage: &dyn Storage, accounts: &[&Addr]) -> Result<(), ContractError> {
+    let config = CONFIG.load(storage)?;
+    if config.paused {
+        return Err(ContractError::Paused {});
+    }
+    for account in accounts {
+        assert_not_frozen(storage, account)?;
+    }
+    Ok(())
+}
+
+fn assert_not_frozen(storage: &dyn Storage, account: &Addr) -> Result<(), ContractError> {
+    if FROZEN.may_load(storage, account)?.unwrap_or(false) {
+        return Err(ContractError::AccountFrozen {
+            address: account.to_string(),
+        });
     }
+    Ok(())
 }
 
-pub fn try_increment(deps: DepsMut) -> Result<Response, ContractError> {
-    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-        state.count += 1;
-        Ok(state)
+/// Burning outside of `Withdraw` would leave coins locked without backing
+/// tokens, so it is refused when the token wraps a native denom.
+fn assert_not_wrapper(storage: &dyn Storage) -> Result<(), ContractError> {
+    let config = CONFIG.load(storage)?;
+    if config.wrapped_denom.is_some() {
+        return Err(ContractError::WrapperMode {});
+    }
+    Ok(())
+}
+
+fn log_supply_change(
+    storage: &mut dyn Storage,
+    change: &SupplyChange,
+) -> Result<(), ContractError> {
+    let id = SUPPLY_LOG_COUNT.may_load(storage)?.unwrap_or_default();
+    SUPPLY_LOG.save(storage, id, change)?;
+    SUPPLY_LOG_COUNT.save(storage, &(id + 1))?;
+    Ok(())
+}
+
+fn mint_balance(
+    storage: &mut dyn Storage,
+    env: &Env,
+    actor: &Addr,
+    to: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let config = CONFIG.load(storage)?;
+    let cap = config.minter.and_then(|minter| minter.cap);
+    TOKEN_INFO.update(storage, |mut info| -> Result<_, ContractError> {
+        info.total_supply = info.total_supply.checked_add(amount)?;
+        if matches!(cap, Some(cap) if info.total_supply > cap) {
+            return Err(ContractError::CannotExceedCap {});
+        }
+        Ok(info)
+    })?;
+    BALANCES.update(storage, to, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_add(amount)?)
+    })?;
+    log_supply_change(
+        storage,
+        &SupplyChange {
+            action: SupplyAction::Mint,
+            height: env.block.height,
+            actor: actor.clone(),
+            account: to.clone(),
+            amount,
+        },
+    )
+}
+
+fn move_balance(
+    storage: &mut dyn Storage,
+    from: &Addr,
+    to: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    BALANCES.update(storage, from, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_sub(amount)?)
+    })?;
+    BALANCES.update(storage, to, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_add(amount)?)
     })?;
+    Ok(())
+}
 
-    Ok(Response::new().add_attribute("method", "try_increment"))
+/// Moves `amount` from `from` to `to`, routing the transfer fee to the fee
+/// recipient unless either side is exempt. Returns the fee that was taken.
+/// Fails while the fee recipient is frozen, as it cannot receive the fee.
+fn transfer_with_fee(
+    storage: &mut dyn Storage,
+    from: &Addr,
+    to: &Addr,
+    amount: Uint128,
+) -> Result<Uint128, ContractError> {
+    let config = CONFIG.load(storage)?;
+    let fee = match config.fee_recipient {
+        Some(fee_recipient)
+            if config.fee_bps > 0
+                && !FEE_EXEMPT.has(storage, from)
+                && !FEE_EXEMPT.has(storage, to) =>
+        {
+            let fee = amount.multiply_ratio(config.fee_bps, MAX_FEE_BPS);
+            if !fee.is_zero() {
+                assert_not_frozen(storage, &fee_recipient)?;
+                move_balance(storage, from, &fee_recipient, fee)?;
+            }
+            fee
+        }
+        _ => Uint128::zero(),
+    };
+    move_balance(storage, from, to, amount.checked_sub(fee)?)?;
+    Ok(fee)
+}
+
+fn burn_balance(
+    storage: &mut dyn Storage,
+    env: &Env,
+    actor: &Addr,
+    from: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    BALANCES.update(storage, from, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_sub(amount)?)
+    })?;
+    TOKEN_INFO.update(storage, |mut info| -> StdResult<_> {
+        info.total_supply = info.total_supply.checked_sub(amount)?;
+        Ok(info)
+    })?;
+    log_supply_change(
+        storage,
+        &SupplyChange {
+            action: SupplyAction::Burn,
+            height: env.block.height,
+            actor: actor.clone(),
+            account: from.clone(),
+            amount,
+        },
+    )
 }
 
-pub fn try_reset(deps: DepsMut, info: MessageInfo, count: i32) -> Result<Response, ContractError> {
-    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-        if info.sender != state.owner {
-            return Err(ContractError::Unauthorized {});
+fn deduct_allowance(
+    storage: &mut dyn Storage,
+    env: &Env,
+    owner: &Addr,
+    spender: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    ALLOWANCES.update(storage, (owner, spender), |allowance| {
+        let mut allowance = allowance.ok_or(ContractError::NoAllowance {})?;
+        if allowance.expires.is_expired(&env.block) {
+            return Err(ContractError::Expired {});
         }
-        state.count = count;
-        Ok(state)
+        allowance.allowance = allowance.allowance.checked_sub(amount)?;
+        Ok(allowance)
     })?;
-    Ok(Response::new().add_attribute("method", "reset"))
+    Ok(())
+}
+
+pub fn try_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let recipient = deps.api.addr_validate(&recipient)?;
+    assert_can_move(deps.storage, &[&info.sender, &recipient])?;
+    let fee = transfer_with_fee(deps.storage, &info.sender, &recipient, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "transfer")
+        .add_attribute("from", info.sender)
+        .add_attribute("to", recipient)
+        .add_attribute("amount", amount)
+        .add_attribute("fee", fee))
+}
+
+pub fn try_burn(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    assert_not_wrapper(deps.storage)?;
+    assert_can_move(deps.storage, &[&info.sender])?;
+    burn_balance(deps.storage, &env, &info.sender, &info.sender, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "burn")
+        .add_attribute("from", info.sender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_send(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+    amount: Uint128,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let contract = deps.api.addr_validate(&contract)?;
+    assert_can_move(deps.storage, &[&info.sender, &contract])?;
+    let fee = transfer_with_fee(deps.storage, &info.sender, &contract, amount)?;
+
+    let receive = Cw20ReceiveMsg {
+        sender: info.sender.to_string(),
+        amount: amount.checked_sub(fee)?,
+        msg,
+    }
+    .into_cosmos_msg(contract.clone())?;
+
+    Ok(Response::new()
+        .add_message(receive)
+        .add_attribute("method", "send")
+        .add_attribute("from", info.sender)
+        .add_attribute("to", contract)
+        .add_attribute("amount", amount)
+        .add_attribute("fee", fee))
+}
+
+pub fn try_mint(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+    if config.minter.map(|minter| minter.minter) != Some(info.sender.clone()) {
+        return Err(ContractError::Unauthorized {});
+    }
+    assert_not_wrapper(deps.storage)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    assert_can_move(deps.storage, &[&recipient])?;
+    mint_balance(deps.storage, &env, &info.sender, &recipient, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "mint")
+        .add_attribute("to", recipient)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_update_minter(
+    deps: DepsMut,
+    info: MessageInfo,
+    new_minter: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    let minter = config.minter.ok_or(ContractError::Unauthorized {})?;
+    if minter.minter != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    config.minter = match new_minter {
+        Some(new_minter) => Some(MinterData {
+            minter: deps.api.addr_validate(&new_minter)?,
+            cap: minter.cap,
+        }),
+        None => None,
+    };
+    CONFIG.save(deps.storage, &config)?;
+
+    let new_minter = config
+        .minter
+        .map(|minter| minter.minter.to_string())
+        .unwrap_or_else(|| "None".to_string());
+    Ok(Response::new()
+        .add_attribute("method", "update_minter")
+        .add_attribute("new_minter", new_minter))
+}
+
+pub fn try_increase_allowance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    amount: Uint128,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+    if spender == info.sender {
+        return Err(ContractError::CannotSetOwnAccount {});
+    }
+    ALLOWANCES.update(
+        deps.storage,
+        (&info.sender, &spender),
+        |allowance| -> Result<_, ContractError> {
+            let mut val = allowance.unwrap_or_default();
+            if let Some(exp) = expires {
+                if exp.is_expired(&env.block) {
+                    return Err(ContractError::Expired {});
+                }
+                val.expires = exp;
+            }
+            val.allowance = val.allowance.checked_add(amount)?;
+            Ok(val)
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "increase_allowance")
+        .add_attribute("owner", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_decrease_allowance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    amount: Uint128,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+    if spender == info.sender {
+        return Err(ContractError::CannotSetOwnAccount {});
+    }
+    let key = (&info.sender, &spender);
+    let mut allowance = ALLOWANCES
+        .may_load(deps.storage, key)?
+        .ok_or(ContractError::NoAllowance {})?;
+    if amount < allowance.allowance {
+        allowance.allowance = allowance.allowance.checked_sub(amount)?;
+        if let Some(exp) = expires {
+            if exp.is_expired(&env.block) {
+                return Err(ContractError::Expired {});
+            }
+            allowance.expires = exp;
+        }
+        ALLOWANCES.save(deps.storage, key, &allowance)?;
+    } else {
+        ALLOWANCES.remove(deps.storage, key);
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "decrease_allowance")
+        .add_attribute("owner", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_transfer_from(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let owner = deps.api.addr_validate(&owner)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+    assert_can_move(deps.storage, &[&info.sender, &owner, &recipient])?;
+    deduct_allowance(deps.storage, &env, &owner, &info.sender, amount)?;
+    let fee = transfer_with_fee(deps.storage, &owner, &recipient, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "transfer_from")
+        .add_attribute("from", owner)
+        .add_attribute("to", recipient)
+        .add_attribute("by", info.sender)
+        .add_attribute("amount", amount)
+        .add_attribute("fee", fee))
+}
+
+pub fn try_send_from(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    contract: String,
+    amount: Uint128,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let owner = deps.api.addr_validate(&owner)?;
+    let contract = deps.api.addr_validate(&contract)?;
+    assert_can_move(deps.storage, &[&info.sender, &owner, &contract])?;
+    deduct_allowance(deps.storage, &env, &owner, &info.sender, amount)?;
+    let fee = transfer_with_fee(deps.storage, &owner, &contract, amount)?;
+
+    let receive = Cw20ReceiveMsg {
+        sender: info.sender.to_string(),
+        amount: amount.checked_sub(fee)?,
+        msg,
+    }
+    .into_cosmos_msg(contract.clone())?;
+
+    Ok(Response::new()
+        .add_message(receive)
+        .add_attribute("method", "send_from")
+        .add_attribute("from", owner)
+        .add_attribute("to", contract)
+        .add_attribute("by", info.sender)
+        .add_attribute("amount", amount)
+        .add_attribute("fee", fee))
+}
+
+pub fn try_burn_from(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    assert_not_wrapper(deps.storage)?;
+    let owner = deps.api.addr_validate(&owner)?;
+    assert_can_move(deps.storage, &[&info.sender, &owner])?;
+    deduct_allowance(deps.storage, &env, &owner, &info.sender, amount)?;
+    burn_balance(deps.storage, &env, &info.sender, &owner, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "burn_from")
+        .add_attribute("from", owner)
+        .add_attribute("by", info.sender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: Option<String>,
+    pauser: Option<String>,
+    freezer: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(&owner)?;
+    }
+    if let Some(pauser) = pauser {
+        config.pauser = Some(deps.api.addr_validate(&pauser)?);
+    }
+    if let Some(freezer) = freezer {
+        config.freezer = Some(deps.api.addr_validate(&freezer)?);
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("method", "update_config"))
+}
+
+pub fn try_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if config.pauser.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    config.paused = paused;
+    CONFIG.save(deps.storage, &config)?;
+
+    let method = if paused { "pause" } else { "unpause" };
+    Ok(Response::new()
+        .add_attribute("method", method)
+        .add_attribute("by", info.sender))
+}
+
+pub fn try_set_frozen(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    frozen: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.freezer.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    let address = deps.api.addr_validate(&address)?;
+    if frozen {
+        FROZEN.save(deps.storage, &address, &true)?;
+    } else {
+        FROZEN.remove(deps.storage, &address);
+    }
+
+    let method = if frozen { "freeze" } else { "unfreeze" };
+    Ok(Response::new()
+        .add_attribute("method", method)
+        .add_attribute("address", address))
+}
+
+pub fn try_deposit(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let denom = config.wrapped_denom.ok_or(ContractError::NotWrapper {})?;
+    let amount = match info.funds.as_slice() {
+        [coin] if coin.denom == denom && !coin.amount.is_zero() => coin.amount,
+        _ => return Err(ContractError::InvalidDeposit { denom }),
+    };
+    assert_can_move(deps.storage, &[&info.sender])?;
+    mint_balance(deps.storage, &env, &info.sender, &info.sender, amount)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "deposit")
+        .add_attribute("to", info.sender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let denom = config.wrapped_denom.ok_or(ContractError::NotWrapper {})?;
+    assert_can_move(deps.storage, &[&info.sender])?;
+    burn_balance(deps.storage, &env, &info.sender, &info.sender, amount)?;
+
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: coins(amount.u128(), denom),
+        })
+        .add_attribute("method", "withdraw")
+        .add_attribute("from", info.sender)
+        .add_attribute("amount", amount))
+}
+
+pub fn try_update_transfer_fee(
+    deps: DepsMut,
+    info: MessageInfo,
+    fee_bps: u16,
+    fee_recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if fee_bps > MAX_FEE_BPS {
+        return Err(ContractError::InvalidFee { fee_bps });
+    }
+    let fee_recipient = optional_addr_validate(deps.as_ref(), fee_recipient)?;
+    match &fee_recipient {
+        Some(fee_recipient) => assert_not_frozen(deps.storage, fee_recipient)?,
+        None if fee_bps > 0 => return Err(ContractError::MissingFeeRecipient { fee_bps }),
+        None => {}
+    }
+    config.fee_bps = fee_bps;
+    config.fee_recipient = fee_recipient;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "update_transfer_fee")
+        .add_attribute("fee_bps", fee_bps.to_string()))
+}
+
+pub fn try_set_fee_exempt(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    exempt: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let address = deps.api.addr_validate(&address)?;
+    if exempt {
+        FEE_EXEMPT.save(deps.storage, &address, &true)?;
+    } else {
+        FEE_EXEMPT.remove(deps.storage, &address);
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "set_fee_exempt")
+        .add_attribute("address", address)
+        .add_attribute("exempt", exempt.to_string()))
+}
+
+/// Derives the account address of a compressed secp256k1 public key the same
+/// way the chain does: ripemd160(sha256(pubkey)), rendered by the chain's Api.
+fn pubkey_to_addr(deps: Deps, pubkey: &[u8]) -> Result<Addr, ContractError> {
+    if pubkey.len() != 33 {
+        return Err(ContractError::InvalidPubkey {});
+    }
+    let hash = Ripemd160::digest(Sha256::digest(pubkey));
+    Ok(deps
+        .api
+        .addr_humanize(&CanonicalAddr::from(hash.as_slice()))?)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn try_permit(
+    deps: DepsMut,
+    env: Env,
+    owner_pubkey: Binary,
+    spender: String,
+    amount: Uint128,
+    expiry: u64,
+    nonce: u64,
+    signature: Binary,
+) -> Result<Response, ContractError> {
+    if env.block.time.seconds() > expiry {
+        return Err(ContractError::PermitExpired {});
+    }
+    let owner = pubkey_to_addr(deps.as_ref(), &owner_pubkey)?;
+    let spender = deps.api.addr_validate(&spender)?;
+    if spender == owner {
+        return Err(ContractError::CannotSetOwnAccount {});
+    }
+    let expected = NONCES.may_load(deps.storage, &owner)?.unwrap_or_default();
+    if nonce != expected {
+        return Err(ContractError::InvalidNonce { expected });
+    }
+
+    let payload = PermitPayload {
+        chain_id: env.block.chain_id,
+        contract: env.contract.address.to_string(),
+        owner: owner.to_string(),
+        spender: spender.to_string(),
+        amount,
+        expiry,
+        nonce,
+    };
+    let hash = Sha256::digest(to_vec(&payload)?);
+    let valid = deps
+        .api
+        .secp256k1_verify(&hash, &signature, &owner_pubkey)
+        .unwrap_or(false);
+    if !valid {
+        return Err(ContractError::InvalidSignature {});
+    }
+
+    NONCES.save(deps.storage, &owner, &(nonce + 1))?;
+    ALLOWANCES.save(
+        deps.storage,
+        (&owner, &spender),
+        &AllowanceResponse {
+            allowance: amount,
+            expires: Expiration::Never {},
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "permit")
+        .add_attribute("owner", owner)
+        .add_attribute("spender", spender)
+        .add_attribute("amount", amount)
+        .add_attribute("nonce", nonce.to_string()))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-        QueryMsg::GetCount {} => to_binary(&query_count(deps)?),
+        QueryMsg::Balance { address } => to_binary(&query_balance(deps, address)?),
+        QueryMsg::TokenInfo {} => to_binary(&query_token_info(deps)?),
+        QueryMsg::Allowance { owner, spender } => {
+            to_binary(&query_allowance(deps, owner, spender)?)
+        }
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Frozen { address } => to_binary(&query_frozen(deps, address)?),
+        QueryMsg::FeeExempt { address } => to_binary(&query_fee_exempt(deps, address)?),
+        QueryMsg::Nonce { owner } => to_binary(&query_nonce(deps, owner)?),
+        QueryMsg::Minter {} => to_binary(&query_minter(deps)?),
+        QueryMsg::MintHistory { start_after, limit } => {
+            to_binary(&query_mint_history(deps, start_after, limit)?)
+        }
     }
 }
 
-fn query_count(deps: Deps) -> StdResult<GetCountResponse> {
-    let state = STATE.load(deps.storage)?;
-    Ok(GetCountResponse { count: state.count })
+fn query_balance(deps: Deps, address: String) -> StdResult<BalanceResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let balance = BALANCES
+        .may_load(deps.storage, &address)?
+        .unwrap_or_default();
+    Ok(BalanceResponse { balance })
+}
+
+fn query_token_info(deps: Deps) -> StdResult<TokenInfoResponse> {
+    let info = TOKEN_INFO.load(deps.storage)?;
+    Ok(TokenInfoResponse {
+        name: info.name,
+        symbol: info.symbol,
+        decimals: info.decimals,
+        total_supply: info.total_supply,
+    })
+}
+
+fn query_allowance(deps: Deps, owner: String, spender: String) -> StdResult<AllowanceResponse> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let spender = deps.api.addr_validate(&spender)?;
+    let allowance = ALLOWANCES
+        .may_load(deps.storage, (&owner, &spender))?
+        .unwrap_or_default();
+    Ok(allowance)
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse {
+        owner: config.owner.to_string(),
+        minter: config.minter.map(|minter| minter.minter.to_string()),
+        pauser: config.pauser.map(|addr| addr.to_string()),
+        freezer: config.freezer.map(|addr| addr.to_string()),
+        paused: config.paused,
+        wrapped_denom: config.wrapped_denom,
+        fee_bps: config.fee_bps,
+        fee_recipient: config.fee_recipient.map(|addr| addr.to_string()),
+    })
+}
+
+fn query_frozen(deps: Deps, address: String) -> StdResult<FrozenResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let frozen = FROZEN.may_load(deps.storage, &address)?.unwrap_or(false);
+    Ok(FrozenResponse { frozen })
+}
+
+fn query_fee_exempt(deps: Deps, address: String) -> StdResult<FeeExemptResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let exempt = FEE_EXEMPT.has(deps.storage, &address);
+    Ok(FeeExemptResponse { exempt })
+}
+
+fn query_nonce(deps: Deps, owner: String) -> StdResult<NonceResponse> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let nonce = NONCES.may_load(deps.storage, &owner)?.unwrap_or_default();
+    Ok(NonceResponse { nonce })
+}
+
+fn query_minter(deps: Deps) -> StdResult<Option<MinterResponse>> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(config.minter.map(|minter| MinterResponse {
+        minter: minter.minter.to_string(),
+        cap: minter.cap,
+    }))
+}
+
+fn query_mint_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<MintHistoryResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let entries = SUPPLY_LOG
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(MintHistoryResponse { entries })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coins, from_binary};
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{coins, from_binary, Api, OwnedDeps, RecoverPubkeyError, VerificationError};
+    use cw20::Cw20Coin;
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::{Signature, SigningKey};
+    use std::marker::PhantomData;
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            name: "Collateral".to_string(),
+            symbol: "COLL".to_string(),
+            decimals: 6,
+            initial_balances: vec![Cw20Coin {
+                address: "alice".to_string(),
+                amount: Uint128::new(1000),
+            }],
+            mint: Some(MinterResponse {
+                minter: "minter".to_string(),
+                cap: None,
+            }),
+            pauser: Some("pauser".to_string()),
+            freezer: Some("freezer".to_string()),
+            wrapped_denom: None,
+        };
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        deps
+    }
+
+    fn balance(deps: Deps, address: &str) -> Uint128 {
+        let res = query(
+            deps,
+            mock_env(),
+            QueryMsg::Balance {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+        let value: BalanceResponse = from_binary(&res).unwrap();
+        value.balance
+    }
 
     #[test]
     fn proper_initialization() {
-        let mut deps = mock_dependencies();
+        let deps = setup();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::TokenInfo {}).unwrap();
+        let value: TokenInfoResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(1000), value.total_supply);
+        assert_eq!(Uint128::new(1000), balance(deps.as_ref(), "alice"));
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(1000, "earth"));
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        let value: ConfigResponse = from_binary(&res).unwrap();
+        assert_eq!("creator", value.owner);
+        assert!(!value.paused);
+    }
+
+    #[test]
+    fn transfer() {
+        let mut deps = setup();
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(300),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(Uint128::new(700), balance(deps.as_ref(), "alice"));
+        assert_eq!(Uint128::new(300), balance(deps.as_ref(), "bob"));
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(17, value.count);
+        // cannot spend more than the balance
+        let msg = ExecuteMsg::Transfer {
+            recipient: "alice".to_string(),
+            amount: Uint128::new(301),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg);
+        assert!(matches!(res, Err(ContractError::Std(_))));
     }
 
     #[test]
-    fn increment() {
-        let mut deps = mock_dependencies();
+    fn pause() {
+        let mut deps = setup();
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(2, "token"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // only the pauser can pause
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            ExecuteMsg::Pause {},
+        );
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap();
 
-        // beneficiary can release it
-        let info = mock_info("anyone", &coins(2, "token"));
-        let msg = ExecuteMsg::Increment {};
-        let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let msgs = vec![
+            ExecuteMsg::Transfer {
+                recipient: "bob".to_string(),
+                amount: Uint128::new(1),
+            },
+            ExecuteMsg::Send {
+                contract: "bob".to_string(),
+                amount: Uint128::new(1),
+                msg: Binary::default(),
+            },
+            ExecuteMsg::Burn {
+                amount: Uint128::new(1),
+            },
+        ];
+        for msg in msgs {
+            let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+            assert!(matches!(res, Err(ContractError::Paused {})));
+        }
+        let msg = ExecuteMsg::Mint {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(1),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("minter", &[]), msg);
+        assert!(matches!(res, Err(ContractError::Paused {})));
 
-        // should increase counter by 1
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(18, value.count);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            ExecuteMsg::Unpause {},
+        )
+        .unwrap();
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(1),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
     }
 
     #[test]
-    fn reset() {
-        let mut deps = mock_dependencies();
+    fn freeze() {
+        let mut deps = setup();
 
-        let msg = InstantiateMsg { count: 17 };
-        let info = mock_info("creator", &coins(2, "token"));
-        let _res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let msg = ExecuteMsg::Freeze {
+            address: "bob".to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            msg.clone(),
+        );
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        execute(deps.as_mut(), mock_env(), mock_info("freezer", &[]), msg).unwrap();
 
-        // beneficiary can release it
-        let unauth_info = mock_info("anyone", &coins(2, "token"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let res = execute(deps.as_mut(), mock_env(), unauth_info, msg);
+        // frozen accounts cannot receive
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(10),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
         match res {
-            Err(ContractError::Unauthorized {}) => {}
-            _ => panic!("Must return unauthorized error"),
+            Err(ContractError::AccountFrozen { address }) => assert_eq!("bob", address),
+            _ => panic!("Must return account frozen error"),
         }
 
-        // only the original creator can reset the counter
-        let auth_info = mock_info("creator", &coins(2, "token"));
-        let msg = ExecuteMsg::Reset { count: 5 };
-        let _res = execute(deps.as_mut(), mock_env(), auth_info, msg).unwrap();
+        // nor spend through an allowance
+        let msg = ExecuteMsg::IncreaseAllowance {
+            spender: "bob".to_string(),
+            amount: Uint128::new(10),
+            expires: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        let msg = ExecuteMsg::BurnFrom {
+            owner: "alice".to_string(),
+            amount: Uint128::new(10),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bob", &[]),
+            msg.clone(),
+        );
+        assert!(matches!(res, Err(ContractError::AccountFrozen { .. })));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("freezer", &[]),
+            ExecuteMsg::Unfreeze {
+                address: "bob".to_string(),
+            },
+        )
+        .unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap();
+        assert_eq!(Uint128::new(990), balance(deps.as_ref(), "alice"));
+    }
+
+    #[test]
+    fn wrapper_mode() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            name: "Wrapped Juno".to_string(),
+            symbol: "WJUNO".to_string(),
+            decimals: 6,
+            initial_balances: vec![],
+            mint: Some(MinterResponse {
+                minter: "minter".to_string(),
+                cap: None,
+            }),
+            pauser: None,
+            freezer: None,
+            wrapped_denom: Some("ujuno".to_string()),
+        };
+        // a minter cap would limit deposits
+        let res = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        );
+        assert!(matches!(res, Err(ContractError::WrapperMode {})));
+        let msg = InstantiateMsg { mint: None, ..msg };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        // only the wrapped denom is accepted
+        let info = mock_info("alice", &coins(100, "uatom"));
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Deposit {});
+        assert!(matches!(res, Err(ContractError::InvalidDeposit { .. })));
+
+        let info = mock_info("alice", &coins(100, "ujuno"));
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Deposit {}).unwrap();
+        assert_eq!(Uint128::new(100), balance(deps.as_ref(), "alice"));
+
+        // supply can only change through deposit and withdraw
+        let msg = ExecuteMsg::Mint {
+            recipient: "alice".to_string(),
+            amount: Uint128::new(1),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("minter", &[]), msg);
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        let msg = ExecuteMsg::Burn {
+            amount: Uint128::new(1),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(res, Err(ContractError::WrapperMode {})));
+
+        // funds sent with other messages would be locked without backing
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(1),
+        };
+        let info = mock_info("alice", &coins(100, "ujuno"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg);
+        assert!(matches!(res, Err(ContractError::FundsNotAccepted {})));
+
+        let msg = ExecuteMsg::Withdraw {
+            amount: Uint128::new(40),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(Uint128::new(60), balance(deps.as_ref(), "alice"));
+    }
+
+    #[test]
+    fn transfer_fee() {
+        let mut deps = setup();
+
+        let msg = ExecuteMsg::UpdateTransferFee {
+            fee_bps: 10_001,
+            fee_recipient: Some("treasury".to_string()),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::InvalidFee { fee_bps: 10_001 })
+        ));
+
+        // a fee needs somewhere to go
+        let msg = ExecuteMsg::UpdateTransferFee {
+            fee_bps: 100,
+            fee_recipient: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert!(matches!(
+            res,
+            Err(ContractError::MissingFeeRecipient { fee_bps: 100 })
+        ));
+
+        let msg = ExecuteMsg::UpdateTransferFee {
+            fee_bps: 100,
+            fee_recipient: Some("treasury".to_string()),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(500),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|a| a.key == "fee" && a.value == "5"));
+        assert_eq!(Uint128::new(500), balance(deps.as_ref(), "alice"));
+        assert_eq!(Uint128::new(495), balance(deps.as_ref(), "bob"));
+        assert_eq!(Uint128::new(5), balance(deps.as_ref(), "treasury"));
+
+        // a frozen recipient can neither be configured nor keep receiving fees
+        let msg = ExecuteMsg::Freeze {
+            address: "treasury".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("freezer", &[]), msg).unwrap();
+        let msg = ExecuteMsg::UpdateTransferFee {
+            fee_bps: 100,
+            fee_recipient: Some("treasury".to_string()),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert!(matches!(res, Err(ContractError::AccountFrozen { .. })));
+        let msg = ExecuteMsg::Transfer {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(100),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg);
+        assert!(matches!(res, Err(ContractError::AccountFrozen { .. })));
+        let msg = ExecuteMsg::Unfreeze {
+            address: "treasury".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("freezer", &[]), msg).unwrap();
+
+        // platform contracts are exempt on either side of the transfer
+        let msg = ExecuteMsg::SetFeeExempt {
+            address: "orderbook".to_string(),
+            exempt: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let msg = ExecuteMsg::Send {
+            contract: "orderbook".to_string(),
+            amount: Uint128::new(100),
+            msg: Binary::default(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert!(res
+            .attributes
+            .iter()
+            .any(|a| a.key == "fee" && a.value == "0"));
+        assert_eq!(Uint128::new(100), balance(deps.as_ref(), "orderbook"));
+    }
+
+    /// MockApi cannot humanize the 20 byte hashes accounts are derived from,
+    /// so permit tests render those as hex instead.
+    #[derive(Copy, Clone, Default)]
+    struct PermitApi(MockApi);
+
+    impl Api for PermitApi {
+        fn addr_validate(&self, human: &str) -> StdResult<Addr> {
+            self.0.addr_validate(human)
+        }
+
+        fn addr_canonicalize(&self, human: &str) -> StdResult<CanonicalAddr> {
+            self.0.addr_canonicalize(human)
+        }
+
+        fn addr_humanize(&self, canonical: &CanonicalAddr) -> StdResult<Addr> {
+            if canonical.len() != 20 {
+                return self.0.addr_humanize(canonical);
+            }
+            let hex: String = canonical.iter().map(|b| format!("{:02x}", b)).collect();
+            Ok(Addr::unchecked(hex))
+        }
+
+        fn secp256k1_verify(
+            &self,
+            message_hash: &[u8],
+            signature: &[u8],
+            public_key: &[u8],
+        ) -> Result<bool, VerificationError> {
+            self.0.secp256k1_verify(message_hash, signature, public_key)
+        }
+
+        fn secp256k1_recover_pubkey(
+            &self,
+            message_hash: &[u8],
+            signature: &[u8],
+            recovery_param: u8,
+        ) -> Result<Vec<u8>, RecoverPubkeyError> {
+            self.0
+                .secp256k1_recover_pubkey(message_hash, signature, recovery_param)
+        }
+
+        fn ed25519_verify(
+            &self,
+            message: &[u8],
+            signature: &[u8],
+            public_key: &[u8],
+        ) -> Result<bool, VerificationError> {
+            self.0.ed25519_verify(message, signature, public_key)
+        }
+
+        fn ed25519_batch_verify(
+            &self,
+            messages: &[&[u8]],
+            signatures: &[&[u8]],
+            public_keys: &[&[u8]],
+        ) -> Result<bool, VerificationError> {
+            self.0
+                .ed25519_batch_verify(messages, signatures, public_keys)
+        }
+
+        fn debug(&self, message: &str) {
+            self.0.debug(message)
+        }
+    }
+
+    #[test]
+    fn permit() {
+        let mut deps = OwnedDeps {
+            storage: MockStorage::default(),
+            api: PermitApi::default(),
+            querier: MockQuerier::default(),
+            custom_query_type: PhantomData,
+        };
+        let msg = InstantiateMsg {
+            name: "Collateral".to_string(),
+            symbol: "COLL".to_string(),
+            decimals: 6,
+            initial_balances: vec![],
+            mint: None,
+            pauser: None,
+            freezer: None,
+            wrapped_denom: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let pubkey = Binary::from(signing_key.verifying_key().to_bytes().as_slice());
+        let owner = pubkey_to_addr(deps.as_ref(), &pubkey).unwrap();
+        let sign = |nonce: u64, amount: u128| {
+            let env = mock_env();
+            let payload = PermitPayload {
+                chain_id: env.block.chain_id,
+                contract: env.contract.address.to_string(),
+                owner: owner.to_string(),
+                spender: "orderbook".to_string(),
+                amount: Uint128::new(amount),
+                expiry: env.block.time.seconds() + 60,
+                nonce,
+            };
+            let signature: Signature = signing_key.sign(&to_vec(&payload).unwrap());
+            ExecuteMsg::Permit {
+                owner_pubkey: pubkey.clone(),
+                spender: payload.spender,
+                amount: payload.amount,
+                expiry: payload.expiry,
+                nonce,
+                signature: Binary::from(signature.as_ref()),
+            }
+        };
+
+        // anyone can relay a valid permit
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer", &[]),
+            sign(0, 50),
+        )
+        .unwrap();
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Allowance {
+                owner: owner.to_string(),
+                spender: "orderbook".to_string(),
+            },
+        )
+        .unwrap();
+        let value: AllowanceResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::new(50), value.allowance);
+
+        // a permit cannot be replayed
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("relayer", &[]),
+            sign(0, 50),
+        );
+        assert!(matches!(
+            res,
+            Err(ContractError::InvalidNonce { expected: 1 })
+        ));
+
+        // nor altered after signing
+        let mut msg = sign(1, 50);
+        if let ExecuteMsg::Permit { ref mut amount, .. } = msg {
+            *amount = Uint128::new(5000);
+        }
+        let res = execute(deps.as_mut(), mock_env(), mock_info("relayer", &[]), msg);
+        assert!(matches!(res, Err(ContractError::InvalidSignature {})));
+
+        // nor submitted after its expiry
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(61);
+        let res = execute(deps.as_mut(), env, mock_info("relayer", &[]), sign(1, 50));
+        assert!(matches!(res, Err(ContractError::PermitExpired {})));
+    }
+
+    #[test]
+    fn minter_cap_and_history() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            name: "Collateral".to_string(),
+            symbol: "COLL".to_string(),
+            decimals: 6,
+            initial_balances: vec![Cw20Coin {
+                address: "alice".to_string(),
+                amount: Uint128::new(100),
+            }],
+            mint: Some(MinterResponse {
+                minter: "minter".to_string(),
+                cap: Some(Uint128::new(150)),
+            }),
+            pauser: None,
+            freezer: None,
+            wrapped_denom: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let mint = |amount: u128| ExecuteMsg::Mint {
+            recipient: "bob".to_string(),
+            amount: Uint128::new(amount),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("minter", &[]),
+            mint(51),
+        );
+        assert!(matches!(res, Err(ContractError::CannotExceedCap {})));
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("minter", &[]),
+            mint(50),
+        )
+        .unwrap();
+
+        // only the current minter can hand over the role, and the cap stays
+        let msg = ExecuteMsg::UpdateMinter {
+            new_minter: Some("minter2".to_string()),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        );
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        execute(deps.as_mut(), mock_env(), mock_info("minter", &[]), msg).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("minter", &[]), mint(1));
+        assert!(matches!(res, Err(ContractError::Unauthorized {})));
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Minter {}).unwrap();
+        let value: Option<MinterResponse> = from_binary(&res).unwrap();
+        assert_eq!(
+            Some(MinterResponse {
+                minter: "minter2".to_string(),
+                cap: Some(Uint128::new(150)),
+            }),
+            value
+        );
+
+        let msg = ExecuteMsg::Burn {
+            amount: Uint128::new(30),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap();
+
+        // initial balance, mint and burn are all logged in order
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::MintHistory {
+                start_after: None,
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+        let value: MintHistoryResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.entries.len());
+        assert_eq!(Addr::unchecked("creator"), value.entries[0].1.actor);
+        assert_eq!(Uint128::new(50), value.entries[1].1.amount);
 
-        // should now be 5
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCount {}).unwrap();
-        let value: GetCountResponse = from_binary(&res).unwrap();
-        assert_eq!(5, value.count);
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::MintHistory {
+                start_after: Some(value.entries[1].0),
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: MintHistoryResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.entries.len());
+        assert_eq!(SupplyAction::Burn, value.entries[0].1.action);
+        assert_eq!(Addr::unchecked("bob"), value.entries[0].1.account);
+        assert_eq!(mock_env().block.height, value.entries[0].1.height);
     }
 }
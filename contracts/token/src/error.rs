@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,11 +6,65 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
     #[error("Custom Error val: {val:?}")]
     CustomError { val: String },
+
+    #[error("Invalid zero amount")]
+    InvalidZeroAmount {},
+
+    #[error("No allowance for this account")]
+    NoAllowance {},
+
+    #[error("Allowance is expired")]
+    Expired {},
+
+    #[error("Cannot set allowance to own account")]
+    CannotSetOwnAccount {},
+
+    #[error("Token transfers are paused")]
+    Paused {},
+
+    #[error("Account {address} is frozen")]
+    AccountFrozen { address: String },
+
+    #[error("Not supported while wrapping a native denom")]
+    WrapperMode {},
+
+    #[error("Token does not wrap a native denom")]
+    NotWrapper {},
+
+    #[error("Only deposits accept funds while wrapping a native denom")]
+    FundsNotAccepted {},
+
+    #[error("Must deposit a non-zero amount of {denom} and nothing else")]
+    InvalidDeposit { denom: String },
+
+    #[error("Transfer fee must be at most 10000 bps, got {fee_bps}")]
+    InvalidFee { fee_bps: u16 },
+
+    #[error("A transfer fee of {fee_bps} bps needs a fee recipient")]
+    MissingFeeRecipient { fee_bps: u16 },
+
+    #[error("Permit public key must be a 33 byte compressed secp256k1 key")]
+    InvalidPubkey {},
+
+    #[error("Permit signature does not match the payload")]
+    InvalidSignature {},
+
+    #[error("Permit nonce is invalid, expected {expected}")]
+    InvalidNonce { expected: u64 },
+
+    #[error("Permit is expired")]
+    PermitExpired {},
+
+    #[error("Minting cannot exceed the cap")]
+    CannotExceedCap {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
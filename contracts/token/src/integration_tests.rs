@@ -1,11 +1,12 @@
 #[cfg(test)]
 mod tests {
-    use crate::helpers::CwTemplateContract;
-    use crate::msg::InstantiateMsg;
+    use crate::helpers::TokenContract;
+    use crate::msg::{InstantiateMsg, QueryMsg};
     use cosmwasm_std::{Addr, Coin, Empty, Uint128};
+    use cw20::{BalanceResponse, Cw20Coin, MinterResponse};
     use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
 
-    pub fn contract_template() -> Box<dyn Contract<Empty>> {
+    pub fn token_contract() -> Box<dyn Contract<Empty>> {
         let contract = ContractWrapper::new(
             crate::contract::execute,
             crate::contract::instantiate,
@@ -14,8 +15,9 @@ mod tests {
         Box::new(contract)
     }
 
-    const USER: &str = "USER";
-    const ADMIN: &str = "ADMIN";
+    const USER: &str = "user";
+    const ADMIN: &str = "admin";
+    const PAUSER: &str = "pauser";
     const NATIVE_DENOM: &str = "denom";
 
     fn mock_app() -> App {
@@ -34,38 +36,145 @@ mod tests {
         })
     }
 
-    fn proper_instantiate() -> (App, CwTemplateContract) {
+    fn proper_instantiate() -> (App, TokenContract) {
         let mut app = mock_app();
-        let cw_template_id = app.store_code(contract_template());
-
-        let msg = InstantiateMsg { count: 1i32 };
-        let cw_template_contract_addr = app
-            .instantiate_contract(
-                cw_template_id,
-                Addr::unchecked(ADMIN),
-                &msg,
-                &[],
-                "test",
-                None,
-            )
+        let token_id = app.store_code(token_contract());
+
+        let msg = InstantiateMsg {
+            name: "Collateral".to_string(),
+            symbol: "COLL".to_string(),
+            decimals: 6,
+            initial_balances: vec![Cw20Coin {
+                address: USER.to_string(),
+                amount: Uint128::new(1000),
+            }],
+            mint: Some(MinterResponse {
+                minter: ADMIN.to_string(),
+                cap: None,
+            }),
+            pauser: Some(PAUSER.to_string()),
+            freezer: Some(ADMIN.to_string()),
+            wrapped_denom: None,
+        };
+        let token_contract_addr = app
+            .instantiate_contract(token_id, Addr::unchecked(ADMIN), &msg, &[], "test", None)
             .unwrap();
 
-        let cw_template_contract = CwTemplateContract(cw_template_contract_addr);
+        let token_contract = TokenContract(token_contract_addr);
 
-        (app, cw_template_contract)
+        (app, token_contract)
     }
 
-    mod count {
+    fn query_balance(app: &App, contract: &TokenContract, address: &str) -> BalanceResponse {
+        app.wrap()
+            .query_wasm_smart(
+                contract.addr(),
+                &QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap()
+    }
+
+    mod transfer {
         use super::*;
         use crate::msg::ExecuteMsg;
+        use crate::ContractError;
 
         #[test]
-        fn count() {
-            let (mut app, cw_template_contract) = proper_instantiate();
+        fn transfer() {
+            let (mut app, token_contract) = proper_instantiate();
 
-            let msg = ExecuteMsg::Increment {};
-            let cosmos_msg = cw_template_contract.call(msg).unwrap();
+            let msg = ExecuteMsg::Transfer {
+                recipient: ADMIN.to_string(),
+                amount: Uint128::new(100),
+            };
+            let cosmos_msg = token_contract.call(msg).unwrap();
             app.execute(Addr::unchecked(USER), cosmos_msg).unwrap();
+
+            let res = query_balance(&app, &token_contract, ADMIN);
+            assert_eq!(res.balance, Uint128::new(100));
+        }
+
+        #[test]
+        fn paused_transfer() {
+            let (mut app, token_contract) = proper_instantiate();
+
+            let cosmos_msg = token_contract.call(ExecuteMsg::Pause {}).unwrap();
+            app.execute(Addr::unchecked(PAUSER), cosmos_msg).unwrap();
+
+            let msg = ExecuteMsg::Transfer {
+                recipient: ADMIN.to_string(),
+                amount: Uint128::new(100),
+            };
+            let cosmos_msg = token_contract.call(msg).unwrap();
+            let err = app.execute(Addr::unchecked(USER), cosmos_msg).unwrap_err();
+            assert!(matches!(
+                err.downcast::<ContractError>().unwrap(),
+                ContractError::Paused {}
+            ));
+        }
+    }
+
+    mod wrapper {
+        use super::*;
+        use crate::msg::ExecuteMsg;
+        use cw20::TokenInfoResponse;
+
+        #[test]
+        fn deposit_and_withdraw() {
+            let mut app = mock_app();
+            let token_id = app.store_code(token_contract());
+            let msg = InstantiateMsg {
+                name: "Wrapped".to_string(),
+                symbol: "WRAP".to_string(),
+                decimals: 6,
+                initial_balances: vec![],
+                mint: None,
+                pauser: None,
+                freezer: None,
+                wrapped_denom: Some(NATIVE_DENOM.to_string()),
+            };
+            let addr = app
+                .instantiate_contract(token_id, Addr::unchecked(ADMIN), &msg, &[], "test", None)
+                .unwrap();
+            let token_contract = TokenContract(addr);
+
+            app.execute_contract(
+                Addr::unchecked(USER),
+                token_contract.addr(),
+                &ExecuteMsg::Deposit {},
+                &[Coin {
+                    denom: NATIVE_DENOM.to_string(),
+                    amount: Uint128::new(1),
+                }],
+            )
+            .unwrap();
+            let info: TokenInfoResponse = app
+                .wrap()
+                .query_wasm_smart(token_contract.addr(), &QueryMsg::TokenInfo {})
+                .unwrap();
+            let locked = app
+                .wrap()
+                .query_balance(token_contract.addr(), NATIVE_DENOM)
+                .unwrap();
+            assert_eq!(info.total_supply, locked.amount);
+            assert_eq!(
+                query_balance(&app, &token_contract, USER).balance,
+                Uint128::new(1)
+            );
+
+            let msg = ExecuteMsg::Withdraw {
+                amount: Uint128::new(1),
+            };
+            app.execute_contract(Addr::unchecked(USER), token_contract.addr(), &msg, &[])
+                .unwrap();
+            let native = app.wrap().query_balance(USER, NATIVE_DENOM).unwrap();
+            assert_eq!(native.amount, Uint128::new(1));
+            assert_eq!(
+                query_balance(&app, &token_contract, USER).balance,
+                Uint128::zero()
+            );
         }
     }
 }
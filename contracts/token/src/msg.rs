@@ -1,27 +1,181 @@
+use cosmwasm_std::{Binary, Uint128};
+use cw20::{Cw20Coin, Expiration, MinterResponse};
+
+use crate::state::SupplyChange;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
-    pub count: i32,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub initial_balances: Vec<Cw20Coin>,
+    pub mint: Option<MinterResponse>,
+    pub pauser: Option<String>,
+    pub freezer: Option<String>,
+    /// Turns the token into a 1:1 wrapper of this native denom. Supply can
+    /// then only change through `Deposit` and `Withdraw`, which is the only
+    /// message accepting funds, and there can be no minter.
+    pub wrapped_denom: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    Increment {},
-    Reset { count: i32 },
+    Transfer {
+        recipient: String,
+        amount: Uint128,
+    },
+    Burn {
+        amount: Uint128,
+    },
+    Send {
+        contract: String,
+        amount: Uint128,
+        msg: Binary,
+    },
+    Mint {
+        recipient: String,
+        amount: Uint128,
+    },
+    /// Hands the minter role to `new_minter`, keeping the cap. `None` disables
+    /// minting for good.
+    UpdateMinter {
+        new_minter: Option<String>,
+    },
+    IncreaseAllowance {
+        spender: String,
+        amount: Uint128,
+        expires: Option<Expiration>,
+    },
+    DecreaseAllowance {
+        spender: String,
+        amount: Uint128,
+        expires: Option<Expiration>,
+    },
+    TransferFrom {
+        owner: String,
+        recipient: String,
+        amount: Uint128,
+    },
+    SendFrom {
+        owner: String,
+        contract: String,
+        amount: Uint128,
+        msg: Binary,
+    },
+    BurnFrom {
+        owner: String,
+        amount: Uint128,
+    },
+    UpdateConfig {
+        owner: Option<String>,
+        pauser: Option<String>,
+        freezer: Option<String>,
+    },
+    Pause {},
+    Unpause {},
+    Freeze {
+        address: String,
+    },
+    Unfreeze {
+        address: String,
+    },
+    Deposit {},
+    Withdraw {
+        amount: Uint128,
+    },
+    UpdateTransferFee {
+        fee_bps: u16,
+        fee_recipient: Option<String>,
+    },
+    SetFeeExempt {
+        address: String,
+        exempt: bool,
+    },
+    /// Sets the allowance of `spender` over the tokens of the account owning
+    /// `owner_pubkey`, authorized by a signature instead of a transaction.
+    Permit {
+        owner_pubkey: Binary,
+        spender: String,
+        amount: Uint128,
+        expiry: u64,
+        nonce: u64,
+        signature: Binary,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    // GetCount returns the current count as a json-encoded number
-    GetCount {},
+    Balance {
+        address: String,
+    },
+    TokenInfo {},
+    Allowance {
+        owner: String,
+        spender: String,
+    },
+    Config {},
+    Frozen {
+        address: String,
+    },
+    FeeExempt {
+        address: String,
+    },
+    Nonce {
+        owner: String,
+    },
+    Minter {},
+    MintHistory {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+/// The payload signed for a `Permit`. Its JSON encoding is hashed with sha256
+/// and signed with the owner's secp256k1 key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PermitPayload {
+    pub chain_id: String,
+    pub contract: String,
+    pub owner: String,
+    pub spender: String,
+    pub amount: Uint128,
+    pub expiry: u64,
+    pub nonce: u64,
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct GetCountResponse {
-    pub count: i32,
+pub struct ConfigResponse {
+    pub owner: String,
+    pub minter: Option<String>,
+    pub pauser: Option<String>,
+    pub freezer: Option<String>,
+    pub paused: bool,
+    pub wrapped_denom: Option<String>,
+    pub fee_bps: u16,
+    pub fee_recipient: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FrozenResponse {
+    pub frozen: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeExemptResponse {
+    pub exempt: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NonceResponse {
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintHistoryResponse {
+    pub entries: Vec<(u64, SupplyChange)>,
 }
@@ -1,13 +1,71 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::Addr;
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Uint128};
+use cw20::AllowanceResponse;
+use cw_storage_plus::{Item, Map};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct State {
-    pub count: i32,
+pub struct Config {
     pub owner: Addr,
+    pub minter: Option<MinterData>,
+    pub pauser: Option<Addr>,
+    pub freezer: Option<Addr>,
+    pub paused: bool,
+    /// Native denom backing the token 1:1 when it runs as a wrapper.
+    pub wrapped_denom: Option<String>,
+    /// Fee charged on transfers, in basis points of the transferred amount.
+    pub fee_bps: u16,
+    pub fee_recipient: Option<Addr>,
 }
 
-pub const STATE: Item<State> = Item::new("state");
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MinterData {
+    pub minter: Addr,
+    /// Total supply can never be minted above this amount.
+    pub cap: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SupplyAction {
+    Mint,
+    Burn,
+}
+
+/// One entry of the mint and burn audit log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SupplyChange {
+    pub action: SupplyAction,
+    pub height: u64,
+    /// The sender that triggered the change.
+    pub actor: Addr,
+    /// The account whose balance was minted to or burned from.
+    pub account: Addr,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenInfo {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub total_supply: Uint128,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+pub const TOKEN_INFO: Item<TokenInfo> = Item::new("token_info");
+
+pub const BALANCES: Map<&Addr, Uint128> = Map::new("balances");
+//key is owner, spender
+pub const ALLOWANCES: Map<(&Addr, &Addr), AllowanceResponse> = Map::new("allowances");
+//addresses that can neither send nor receive tokens
+pub const FROZEN: Map<&Addr, bool> = Map::new("frozen");
+//addresses that neither pay nor trigger the transfer fee
+pub const FEE_EXEMPT: Map<&Addr, bool> = Map::new("fee_exempt");
+//next permit nonce expected from each owner
+pub const NONCES: Map<&Addr, u64> = Map::new("nonces");
+
+//key is a sequential id, starting from 0
+pub const SUPPLY_LOG: Map<u64, SupplyChange> = Map::new("supply_log");
+pub const SUPPLY_LOG_COUNT: Item<u64> = Item::new("supply_log_count");